@@ -5,17 +5,20 @@
 
 echo "🚚 Adding supply chain events for testing..."
 
-# Function to add supply chain event
+# Function to add supply chain event as `identity` (the product's manufacturer or
+# current custodian). Certifications are registry IDs such as "CERT-000001", not
+# free text; pass "vec {}" when none apply.
 add_event() {
-    local product_id=$1
-    local stage=$2
-    local location=$3
-    local status=$4
-    local details=$5
-    local certs=$6
+    local identity=$1
+    local product_id=$2
+    local stage=$3
+    local location=$4
+    local status=$5
+    local details=$6
+    local certs=$7
     
     echo "📍 Adding $stage event for product $product_id"
-    dfx canister call supply_chain_backend add_supply_chain_event "(record { 
+    dfx --identity "$identity" canister call supply_chain_backend add_supply_chain_event "(record { 
         product_id=\"$product_id\"; 
         stage=variant { $stage }; 
         location=\"$location\"; 
@@ -31,77 +34,81 @@ add_event() {
 # Get a product ID first (you'll need to replace this with actual IDs from your deployment)
 echo "📋 First, let's get some product IDs..."
 echo "Run this command to see available products:"
-echo "dfx canister call supply_chain_backend search_products '(record { name=null; category=null; manufacturer=null; status=null; limit=opt 5 })'"
+echo "dfx canister call supply_chain_backend search_products '(record { name=null; category=null; manufacturer=null; manufacturer_id=null; organisation_id=null; status=null; batch_number=null; production_date_from=null; production_date_to=null; updated_from=null; updated_to=null; certifications=null; raw_materials=null; location=null; sort_by=null; sort_order=null; limit=opt 5; cursor=null })'"
 echo ""
-echo "Then replace PRODUCT_ID_HERE in the examples below with actual product IDs"
+echo "Then replace PRODUCT_ID_HERE in the examples below with actual product IDs."
+echo "The examples run as seed-john, the manufacturer of the apparel products created by test_data.sh."
 echo ""
 
 # Example events (replace PRODUCT_ID_HERE with actual product IDs)
 cat << 'EOF'
-# Example commands to add supply chain events:
+# Example commands to add supply chain events. Certifications must be IDs of
+# registry certifications covering the product (see issue_certification); the
+# batch certifications issued by test_data.sh can be listed with:
+# dfx canister call supply_chain_backend list_certifications '(null, null)'
 
 # Manufacturing stage
-dfx canister call supply_chain_backend add_supply_chain_event "(record { 
+dfx --identity seed-john canister call supply_chain_backend add_supply_chain_event "(record { 
     product_id=\"PRODUCT_ID_HERE\"; 
     stage=variant { Manufacturing }; 
     location=\"Mumbai, India\"; 
     status=variant { Completed }; 
     details=\"Manufacturing completed with quality checks\"; 
-    certifications=vec { \"ISO 9001\"; \"Quality Approved\" }; 
+    certifications=vec {}; 
     estimated_arrival=null; 
     metadata=vec {} 
 })"
 
 # Quality Control stage
-dfx canister call supply_chain_backend add_supply_chain_event "(record { 
+dfx --identity seed-john canister call supply_chain_backend add_supply_chain_event "(record { 
     product_id=\"PRODUCT_ID_HERE\"; 
     stage=variant { QualityControl }; 
     location=\"Mumbai, India\"; 
     status=variant { Completed }; 
     details=\"Passed all quality control tests\"; 
-    certifications=vec { \"QC Approved\"; \"Safety Tested\" }; 
+    certifications=vec {}; 
     estimated_arrival=null; 
     metadata=vec {} 
 })"
 
 # Packaging stage
-dfx canister call supply_chain_backend add_supply_chain_event "(record { 
+dfx --identity seed-john canister call supply_chain_backend add_supply_chain_event "(record { 
     product_id=\"PRODUCT_ID_HERE\"; 
     stage=variant { Packaging }; 
     location=\"Mumbai, India\"; 
     status=variant { Completed }; 
     details=\"Packaged with eco-friendly materials\"; 
-    certifications=vec { \"Eco-Packaging\" }; 
+    certifications=vec {}; 
     estimated_arrival=null; 
     metadata=vec {} 
 })"
 
 # Shipping stage
-dfx canister call supply_chain_backend add_supply_chain_event "(record { 
+dfx --identity seed-john canister call supply_chain_backend add_supply_chain_event "(record { 
     product_id=\"PRODUCT_ID_HERE\"; 
     stage=variant { Shipping }; 
     location=\"Mumbai Port, India\"; 
     status=variant { InProgress }; 
     details=\"Shipped via container vessel to Los Angeles\"; 
-    certifications=vec { \"Export Certificate\" }; 
+    certifications=vec {}; 
     estimated_arrival=opt 1704153600000000000; 
     metadata=vec { record { \"carrier\"; \"GlobalShip Logistics\" }; record { \"vessel\"; \"MV Ocean Star\" } } 
 })"
 
 # Distribution stage
-dfx canister call supply_chain_backend add_supply_chain_event "(record { 
+dfx --identity seed-john canister call supply_chain_backend add_supply_chain_event "(record { 
     product_id=\"PRODUCT_ID_HERE\"; 
     stage=variant { Distribution }; 
     location=\"Los Angeles, CA, USA\"; 
     status=variant { Completed }; 
     details=\"Arrived at distribution center and sorted\"; 
-    certifications=vec { \"Import Cleared\" }; 
+    certifications=vec {}; 
     estimated_arrival=null; 
     metadata=vec { record { \"warehouse\"; \"LA Distribution Hub\" } } 
 })"
 
 # Retail stage
-dfx canister call supply_chain_backend add_supply_chain_event "(record { 
+dfx --identity seed-john canister call supply_chain_backend add_supply_chain_event "(record { 
     product_id=\"PRODUCT_ID_HERE\"; 
     stage=variant { Retail }; 
     location=\"San Francisco, CA, USA\"; 
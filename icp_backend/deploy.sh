@@ -15,9 +15,10 @@ fi
 echo "🔄 Starting local replica..."
 dfx start --background --clean
 
-# Deploy the canister
+# Deploy the canister with the current identity as the initial admin
 echo "📦 Building and deploying canister..."
-dfx deploy supply_chain_backend
+ADMIN_PRINCIPAL=$(dfx identity get-principal)
+dfx deploy supply_chain_backend --argument "(opt record { admins = vec { principal \"$ADMIN_PRINCIPAL\" } })"
 
 # Get canister ID
 CANISTER_ID=$(dfx canister id supply_chain_backend)
@@ -31,6 +32,6 @@ dfx canister call supply_chain_backend get_canister_status
 echo "🎉 Deployment complete!"
 echo ""
 echo "📚 Next steps:"
-echo "1. Register a user: dfx canister call supply_chain_backend register_user '(record { email=\"test@example.com\"; first_name=\"Test\"; last_name=\"User\"; company=\"Test Company\"; role=variant { Manufacturer } })'"
-echo "2. Register a product: dfx canister call supply_chain_backend register_product '(record { name=\"Test Product\"; category=\"Electronics\"; description=opt \"A test product\"; batch_number=opt \"BATCH001\"; production_date=1640995200000000000; manufacturing_location=\"Factory A\"; raw_materials=vec { \"Silicon\"; \"Plastic\" }; certifications=vec { \"ISO 9001\" }; sustainability_score=opt 85.0; estimated_value=opt 100.0 })'"
+echo "1. Register a user: dfx canister call supply_chain_backend register_user '(record { email=\"test@example.com\"; first_name=\"Test\"; last_name=\"User\"; company=\"Test Company\"; role=variant { Admin } })'"
+echo "2. Register a product: dfx canister call supply_chain_backend register_product '(record { name=\"Test Product\"; category=\"Electronics\"; description=opt \"A test product\"; batch_number=opt \"BATCH001\"; production_date=1640995200000000000; manufacturing_location=\"Factory A\"; raw_materials=vec { \"Silicon\"; \"Plastic\" }; certifications=vec {}; sustainability_score=opt 85.0; estimated_value=opt 100.0 })'"
 echo "3. View analytics: dfx canister call supply_chain_backend get_analytics"
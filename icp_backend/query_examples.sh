@@ -32,20 +32,23 @@ run_query "Get Current User Info" \
 
 # Product queries
 run_query "Search All Products (limit 5)" \
-    "dfx canister call supply_chain_backend search_products '(record { name=null; category=null; manufacturer=null; status=null; limit=opt 5 })'"
+    "dfx canister call supply_chain_backend search_products '(record { name=null; category=null; manufacturer=null; manufacturer_id=null; organisation_id=null; status=null; batch_number=null; production_date_from=null; production_date_to=null; updated_from=null; updated_to=null; certifications=null; raw_materials=null; location=null; sort_by=null; sort_order=null; limit=opt 5; cursor=null })'"
 
 run_query "Search Products by Category (Apparel)" \
-    "dfx canister call supply_chain_backend search_products '(record { name=null; category=opt \"Apparel\"; manufacturer=null; status=null; limit=opt 10 })'"
+    "dfx canister call supply_chain_backend search_products '(record { name=null; category=opt \"Apparel\"; manufacturer=null; manufacturer_id=null; organisation_id=null; status=null; batch_number=null; production_date_from=null; production_date_to=null; updated_from=null; updated_to=null; certifications=null; raw_materials=null; location=null; sort_by=null; sort_order=null; limit=opt 10; cursor=null })'"
 
 run_query "Search Products by Name (Cotton)" \
-    "dfx canister call supply_chain_backend search_products '(record { name=opt \"Cotton\"; category=null; manufacturer=null; status=null; limit=opt 10 })'"
+    "dfx canister call supply_chain_backend search_products '(record { name=opt \"Cotton\"; category=null; manufacturer=null; manufacturer_id=null; organisation_id=null; status=null; batch_number=null; production_date_from=null; production_date_to=null; updated_from=null; updated_to=null; certifications=null; raw_materials=null; location=null; sort_by=null; sort_order=null; limit=opt 10; cursor=null })'"
 
 run_query "Search Products by Status (Manufacturing)" \
-    "dfx canister call supply_chain_backend search_products '(record { name=null; category=null; manufacturer=null; status=opt variant { Manufacturing }; limit=opt 10 })'"
+    "dfx canister call supply_chain_backend search_products '(record { name=null; category=null; manufacturer=null; manufacturer_id=null; organisation_id=null; status=opt variant { Manufacturing }; batch_number=null; production_date_from=null; production_date_to=null; updated_from=null; updated_to=null; certifications=null; raw_materials=null; location=null; sort_by=null; sort_order=null; limit=opt 10; cursor=null })'"
+
+run_query "Most Sustainable Apparel First (page of 10)" \
+    "dfx canister call supply_chain_backend search_products '(record { name=null; category=opt \"Apparel\"; manufacturer=null; manufacturer_id=null; organisation_id=null; status=null; batch_number=null; production_date_from=null; production_date_to=null; updated_from=null; updated_to=null; certifications=null; raw_materials=null; location=null; sort_by=opt variant { EthicalScore }; sort_order=opt variant { Descending }; limit=opt 10; cursor=null })'"
 
 # Partner queries
 run_query "Get All Partners" \
-    "dfx canister call supply_chain_backend get_partners"
+    "dfx canister call supply_chain_backend get_partners '(null)'"
 
 echo ""
 echo "💡 Additional Commands You Can Try:"
@@ -54,11 +57,14 @@ echo "🔸 Get specific product details:"
 echo "   dfx canister call supply_chain_backend get_product '(\"PRODUCT_ID_HERE\")'"
 echo ""
 echo "🔸 Get supply chain events for a product:"
-echo "   dfx canister call supply_chain_backend get_supply_chain_events '(\"PRODUCT_ID_HERE\")'"
+echo "   dfx canister call supply_chain_backend get_supply_chain_events '(\"PRODUCT_ID_HERE\", opt record { cursor=null; limit=opt 20 })'"
+echo ""
+echo "🔸 Continue a list from the next_cursor of the previous page:"
+echo "   dfx canister call supply_chain_backend get_supply_chain_events '(\"PRODUCT_ID_HERE\", opt record { cursor=opt \"NEXT_CURSOR_HERE\"; limit=opt 20 })'"
 echo ""
 echo "🔸 Register a new user:"
 echo "   dfx canister call supply_chain_backend register_user '(record { email=\"test@example.com\"; first_name=\"Test\"; last_name=\"User\"; company=\"Test Company\"; role=variant { Consumer } })'"
 echo ""
 echo "🔸 Register a new product:"
-echo "   dfx canister call supply_chain_backend register_product '(record { name=\"Test Product\"; category=\"Test\"; description=opt \"A test product\"; batch_number=opt \"TEST001\"; production_date=1704067200000000000; manufacturing_location=\"Test Location\"; raw_materials=vec { \"Test Material\" }; certifications=vec { \"Test Cert\" }; sustainability_score=opt 80.0; estimated_value=opt 50.0 })'"
+echo "   dfx canister call supply_chain_backend register_product '(record { name=\"Test Product\"; category=\"Test\"; description=opt \"A test product\"; batch_number=opt \"TEST001\"; production_date=1704067200000000000; manufacturing_location=\"Test Location\"; raw_materials=vec { \"Test Material\" }; certifications=vec {}; sustainability_score=opt 80.0; estimated_value=opt 50.0 })'"
 echo ""
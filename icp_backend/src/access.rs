@@ -0,0 +1,269 @@
+use crate::reputation::partners_for_actor;
+use crate::storage::Key;
+use crate::types::{AccessGrant, PartnerStatus, Product, ProductAccess, ProductChange, ProductVisibility, SupplyChainEvent};
+use crate::{get_user_by_principal, is_admin, EVENT_ACTORS, PRODUCT_ACCESS};
+use candid::Principal;
+
+// How much of a product a caller may see. Stakeholders (manufacturer or its
+// organisation, custodian, pending recipient, anyone who recorded an event),
+// admins and grantees see everything; verified partners see PartnersOnly
+// products in full; everyone else gets the redacted view of public products
+// and nothing of the rest.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccessLevel {
+    Full,
+    Redacted,
+    Hidden,
+}
+
+pub fn product_access(product_id: &str) -> ProductAccess {
+    PRODUCT_ACCESS.with(|access| access.borrow().get(&product_id.to_string()))
+        .unwrap_or(ProductAccess {
+            visibility: ProductVisibility::Public,
+            grants: Vec::new(),
+        })
+}
+
+// What access checks need to know about the caller. It is resolved once per
+// call, so checking each further product only reads that product's own entries.
+pub struct Viewer {
+    pub principal: Principal,
+    pub is_admin: bool,
+    organisation_id: Option<String>,
+    // Partners the viewer acts for. Only verified partners count; suspended and
+    // removed partners lose access.
+    partner_ids: Vec<String>,
+}
+
+impl Viewer {
+    pub fn resolve(principal: Principal) -> Self {
+        Viewer {
+            principal,
+            is_admin: is_admin(&principal).unwrap_or(false),
+            organisation_id: get_user_by_principal(&principal).ok().and_then(|user| user.organisation_id),
+            partner_ids: partners_for_actor(&principal)
+                .into_iter()
+                .filter(|partner| partner.status == PartnerStatus::Verified)
+                .map(|partner| partner.id)
+                .collect(),
+        }
+    }
+}
+
+pub fn access_level(product: &Product, viewer: &Viewer) -> AccessLevel {
+    let access = product_access(&product.id);
+
+    if viewer.is_admin || is_stakeholder(product, viewer) || is_granted(&access.grants, viewer) {
+        return AccessLevel::Full;
+    }
+
+    let is_partner = !viewer.partner_ids.is_empty();
+    match access.visibility {
+        ProductVisibility::Public if is_partner => AccessLevel::Full,
+        ProductVisibility::Public => AccessLevel::Redacted,
+        ProductVisibility::PartnersOnly if is_partner => AccessLevel::Full,
+        ProductVisibility::PartnersOnly | ProductVisibility::Private => AccessLevel::Hidden,
+    }
+}
+
+// Consumers see neither the commercial value nor who currently holds the goods
+pub fn redact_product(mut product: Product) -> Product {
+    product.estimated_value = None;
+    product.custodian = None;
+    product.pending_handoff = None;
+    product
+}
+
+// Redacted events keep their stored hashes, but metadata and estimated_arrival
+// feed compute_event_hash, so a redacted event with either set cannot be
+// rehashed by its reader. Redacted viewers can still check the links between
+// hashes and the certified head, and rely on verify_product_history, which
+// recomputes every hash from the full events, for the contents.
+pub fn redact_event(mut event: SupplyChainEvent) -> SupplyChainEvent {
+    event.metadata.clear();
+    event.estimated_arrival = None;
+    event
+}
+
+// Product fields the redacted view hides, as named in change logs
+const REDACTED_PRODUCT_FIELDS: [&str; 1] = ["estimated_value"];
+
+// Drops edits to hidden fields; None when nothing visible changed
+pub fn redact_change(mut change: ProductChange) -> Option<ProductChange> {
+    change.changes.retain(|field_change| !REDACTED_PRODUCT_FIELDS.contains(&field_change.field.as_str()));
+    (!change.changes.is_empty()).then_some(change)
+}
+
+fn is_stakeholder(product: &Product, viewer: &Viewer) -> bool {
+    let acts_for_manufacturer = match &product.organisation_id {
+        Some(organisation_id) => viewer.organisation_id.as_ref() == Some(organisation_id),
+        None => product.manufacturer_id == viewer.principal,
+    };
+    acts_for_manufacturer
+        || product.custodian == Some(viewer.principal)
+        || product.pending_handoff.as_ref().is_some_and(|handoff| handoff.to == viewer.principal)
+        || EVENT_ACTORS.with(|actors| actors.borrow().contains_key(&Key(product.id.clone(), viewer.principal)))
+}
+
+fn is_granted(grants: &[AccessGrant], viewer: &Viewer) -> bool {
+    grants.iter().any(|grant| match grant {
+        AccessGrant::Principal(principal) => *principal == viewer.principal,
+        AccessGrant::Partner(partner_id) => viewer.partner_ids.contains(partner_id),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{CustodyHandoff, ProductStatus};
+    use std::collections::HashMap;
+
+    const MANUFACTURER: u8 = 1;
+    const CUSTODIAN: u8 = 2;
+    const EVENT_ACTOR: u8 = 3;
+    const GRANTEE: u8 = 4;
+    const PARTNER_MEMBER: u8 = 5;
+    const GRANTED_PARTNER_MEMBER: u8 = 6;
+    const STRANGER: u8 = 7;
+    const RECIPIENT: u8 = 8;
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id])
+    }
+
+    fn viewer(id: u8) -> Viewer {
+        let partner_ids = match id {
+            PARTNER_MEMBER => vec!["PTR-000001".to_string()],
+            GRANTED_PARTNER_MEMBER => vec!["PTR-000002".to_string()],
+            _ => Vec::new(),
+        };
+        Viewer { principal: principal(id), is_admin: false, organisation_id: None, partner_ids }
+    }
+
+    fn product(product_id: &str, visibility: ProductVisibility) -> Product {
+        PRODUCT_ACCESS.with(|access| {
+            access.borrow_mut().insert(product_id.to_string(), ProductAccess {
+                visibility,
+                grants: vec![AccessGrant::Principal(principal(GRANTEE)), AccessGrant::Partner("PTR-000002".to_string())],
+            })
+        });
+        EVENT_ACTORS.with(|actors| actors.borrow_mut().insert(Key(product_id.to_string(), principal(EVENT_ACTOR)), ()));
+
+        Product {
+            id: product_id.to_string(),
+            name: "Linen shirt".to_string(),
+            category: "Apparel".to_string(),
+            description: None,
+            manufacturer: "Acme".to_string(),
+            manufacturer_id: principal(MANUFACTURER),
+            batch_number: None,
+            production_date: 0,
+            raw_materials: Vec::new(),
+            certifications: Vec::new(),
+            sustainability_score: None,
+            estimated_value: Some(42.0),
+            current_status: ProductStatus::InTransit,
+            current_location: "Porto".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            custodian: Some(principal(CUSTODIAN)),
+            pending_handoff: Some(CustodyHandoff {
+                from: principal(CUSTODIAN),
+                to: principal(RECIPIENT),
+                initiated_at: 0,
+                event_id: "EVT-00000001".to_string(),
+            }),
+            organisation_id: None,
+            certification_alerts: None,
+        }
+    }
+
+    fn levels(product: &Product) -> Vec<AccessLevel> {
+        [MANUFACTURER, CUSTODIAN, RECIPIENT, EVENT_ACTOR, GRANTEE, GRANTED_PARTNER_MEMBER, PARTNER_MEMBER, STRANGER]
+            .iter()
+            .map(|id| access_level(product, &viewer(*id)))
+            .collect()
+    }
+
+    #[test]
+    fn public_products_are_redacted_only_for_strangers() {
+        use AccessLevel::*;
+        let product = product("CT-2024-000001", ProductVisibility::Public);
+
+        assert_eq!(levels(&product), vec![Full, Full, Full, Full, Full, Full, Full, Redacted]);
+    }
+
+    #[test]
+    fn partners_only_products_are_hidden_from_strangers() {
+        use AccessLevel::*;
+        let product = product("CT-2024-000002", ProductVisibility::PartnersOnly);
+
+        assert_eq!(levels(&product), vec![Full, Full, Full, Full, Full, Full, Full, Hidden]);
+    }
+
+    #[test]
+    fn private_products_are_hidden_from_ungranted_partners() {
+        use AccessLevel::*;
+        let product = product("CT-2024-000003", ProductVisibility::Private);
+
+        assert_eq!(levels(&product), vec![Full, Full, Full, Full, Full, Full, Hidden, Hidden]);
+    }
+
+    #[test]
+    fn admins_see_private_products() {
+        let product = product("CT-2024-000004", ProductVisibility::Private);
+        let admin = Viewer { is_admin: true, ..viewer(STRANGER) };
+
+        assert_eq!(access_level(&product, &admin), AccessLevel::Full);
+    }
+
+    #[test]
+    fn organisation_members_act_for_the_manufacturer() {
+        let mut product = product("CT-2024-000005", ProductVisibility::Private);
+        product.organisation_id = Some("ORG-000001".to_string());
+        let member = Viewer { organisation_id: Some("ORG-000001".to_string()), ..viewer(STRANGER) };
+
+        assert_eq!(access_level(&product, &member), AccessLevel::Full);
+        // Once the product belongs to an organisation, only its members count
+        assert_eq!(access_level(&product, &viewer(MANUFACTURER)), AccessLevel::Hidden);
+    }
+
+    #[test]
+    fn redacted_product_hides_value_and_custody() {
+        let product = redact_product(product("CT-2024-000006", ProductVisibility::Public));
+
+        assert_eq!(product.estimated_value, None);
+        assert_eq!(product.custodian, None);
+        assert!(product.pending_handoff.is_none());
+        assert_eq!(product.current_location, "Porto");
+    }
+
+    #[test]
+    fn redacted_event_hides_metadata_and_arrival_but_keeps_hashes() {
+        let event = SupplyChainEvent {
+            id: "EVT-00000001".to_string(),
+            product_id: "CT-2024-000007".to_string(),
+            stage: crate::types::SupplyChainStage::Shipping,
+            location: "Porto".to_string(),
+            timestamp: 1,
+            actor: "Acme Logistics".to_string(),
+            actor_id: principal(EVENT_ACTOR),
+            status: crate::types::EventStatus::InProgress,
+            details: "Loaded".to_string(),
+            certifications: Vec::new(),
+            estimated_arrival: Some(2),
+            metadata: HashMap::from([("carrier".to_string(), "GlobalShip".to_string())]),
+            kind: None,
+            lot_id: None,
+            previous_hash: "previous".to_string(),
+            hash: "hash".to_string(),
+            superseded_by: None,
+        };
+        let redacted = redact_event(event);
+
+        assert!(redacted.metadata.is_empty());
+        assert_eq!(redacted.estimated_arrival, None);
+        assert_eq!((redacted.previous_hash.as_str(), redacted.hash.as_str()), ("previous", "hash"));
+        assert_eq!(redacted.details, "Loaded");
+    }
+}
@@ -0,0 +1,295 @@
+use crate::types::{
+    Certification, CertificationAlert, CertificationHolder, CertificationState, CertificationSubject, Notification,
+    Partner, PartnerStatus, Product,
+};
+use crate::{acts_for_holder, acts_for_partner, calculate_ethical_score, next_id_sequence, store_partner, store_product};
+use crate::storage::Key;
+use crate::{
+    Memory, CERTIFICATIONS, CERTIFICATION_SCAN_CURSOR, FACILITIES, NOTIFICATIONS, PARTNERS, PARTNER_CERTIFICATIONS,
+    PRODUCTS, PRODUCT_CERTIFICATIONS,
+};
+use candid::Principal;
+use ic_cdk::api::time;
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+use std::thread::LocalKey;
+use std::time::Duration;
+
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Holders are warned this long before a certification lapses
+const EXPIRY_WARNING_NANOS: u64 = Duration::from_secs(30 * 24 * 60 * 60).as_nanos() as u64;
+
+// Certifications inspected per scan execution
+const SCAN_BATCH_SIZE: usize = 200;
+
+type ReferenceIndex = RefCell<StableBTreeMap<Key<String, String>, (), Memory>>;
+
+// ID of an affected product or partner, with a product's recalculated ethical score
+type Affected = (String, Option<f64>);
+
+// What a certification reference is being attached to. A reference is valid when
+// the certification is in force at `now` and its subject matches the context.
+pub struct CertificationContext<'a> {
+    pub product_id: Option<&'a str>,
+    pub batch_number: Option<&'a str>,
+    // Owner of the product: its manufacturer and owning organisation
+    pub manufacturer_id: Principal,
+    pub organisation_id: Option<&'a str>,
+    pub location: &'a str,
+    pub actor_id: Principal,
+    pub now: u64,
+}
+
+pub fn get_certification(certification_id: &str) -> Option<Certification> {
+    CERTIFICATIONS.with(|certifications| certifications.borrow().get(&certification_id.to_string()))
+}
+
+// References recorded before the registry existed are free text; they are
+// shown as they are
+pub fn certification_label(reference: &str) -> String {
+    get_certification(reference).map_or_else(|| reference.to_string(), |certification| certification.standard)
+}
+
+// Ok when the certification can be relied on at `now`, otherwise why not
+pub fn is_in_force(certification: &Certification, now: u64) -> Result<(), &'static str> {
+    if certification.revoked_at.is_some() {
+        return Err("has been revoked");
+    }
+    if now < certification.valid_from || certification.valid_until < now {
+        return Err("is not valid at this time");
+    }
+    Ok(())
+}
+
+pub fn validate_references(references: &[String], context: &CertificationContext) -> Result<(), String> {
+    for reference in references {
+        let certification = get_certification(reference)
+            .ok_or_else(|| format!("Certification {} is not in the registry", reference))?;
+
+        is_in_force(&certification, context.now)
+            .map_err(|reason| format!("Certification {} {}", reference, reason))?;
+
+        if !covers(&certification.subject, context) {
+            return Err(format!("Certification {} does not cover this subject", reference));
+        }
+    }
+    Ok(())
+}
+
+fn covers(subject: &CertificationSubject, context: &CertificationContext) -> bool {
+    match subject {
+        CertificationSubject::Product(product_id) => context.product_id == Some(product_id.as_str()),
+        CertificationSubject::Batch { batch_number, manufacturer } => {
+            let owned = match manufacturer {
+                CertificationHolder::Principal(principal) => context.manufacturer_id == *principal,
+                CertificationHolder::Organisation(organisation_id) => {
+                    context.organisation_id == Some(organisation_id.as_str())
+                }
+            };
+            owned && context.batch_number == Some(batch_number.as_str())
+        }
+        CertificationSubject::Partner(partner_id) => PARTNERS
+            .with(|partners| partners.borrow().get(partner_id))
+            .is_some_and(|partner| {
+                partner.status != PartnerStatus::Removed && acts_for_partner(&partner, &context.actor_id)
+            }),
+        CertificationSubject::Facility(facility_id) => FACILITIES
+            .with(|facilities| facilities.borrow().get(facility_id))
+            .is_some_and(|facility| {
+                acts_for_holder(&facility.owner, &context.actor_id)
+                    && facility.location.trim().eq_ignore_ascii_case(context.location.trim())
+            }),
+    }
+}
+
+pub fn state_at(certification: &Certification, now: u64) -> Option<CertificationState> {
+    if certification.revoked_at.is_some() {
+        Some(CertificationState::Revoked)
+    } else if now > certification.valid_until {
+        Some(CertificationState::Expired)
+    } else if certification.valid_until - now <= EXPIRY_WARNING_NANOS {
+        Some(CertificationState::Expiring)
+    } else {
+        None
+    }
+}
+
+// Run by the periodic timer. Certifications whose state changed since the last
+// run are flagged on the products and partners referencing them or issued for
+// them, and the holders are notified with the affected products' recalculated
+// scores. Batch and facility certifications nobody references have no holder to
+// notify. Each execution inspects at most SCAN_BATCH_SIZE certifications; a
+// pass started by the timer continues in follow-up executions from the cursor
+// in stable memory until every certification has been inspected.
+pub fn scan_certifications() {
+    let starts_pass = CERTIFICATION_SCAN_CURSOR.with(|cursor| cursor.borrow().get().is_empty());
+    scan_batch(starts_pass);
+}
+
+// `continues` is false when the timer fires while an earlier pass is still
+// being continued, so that only one chain of follow-ups runs at a time
+fn scan_batch(continues: bool) {
+    let now = time();
+    let after = CERTIFICATION_SCAN_CURSOR.with(|cursor| cursor.borrow().get().clone());
+    let start = if after.is_empty() { Bound::Unbounded } else { Bound::Excluded(after) };
+    let batch: Vec<(String, Certification)> = CERTIFICATIONS.with(|certifications| {
+        certifications.borrow().range((start, Bound::Unbounded)).take(SCAN_BATCH_SIZE).collect()
+    });
+
+    let finished = batch.len() < SCAN_BATCH_SIZE;
+    let cursor = match batch.last() {
+        Some((certification_id, _)) if !finished => certification_id.clone(),
+        _ => String::new(),
+    };
+    CERTIFICATION_SCAN_CURSOR.with(|stored| {
+        stored.borrow_mut().set(cursor).expect("Failed to store certification scan cursor");
+    });
+
+    for (certification_id, mut certification) in batch {
+        let Some(state) = state_at(&certification, now) else {
+            continue;
+        };
+        if certification.notified_state == Some(state) {
+            continue;
+        }
+        flag_references(&certification, state, now);
+        certification.notified_state = Some(state);
+        CERTIFICATIONS.with(|certifications| certifications.borrow_mut().insert(certification_id, certification));
+    }
+
+    if !finished && continues {
+        ic_cdk_timers::set_timer(Duration::ZERO, || scan_batch(true));
+    }
+}
+
+fn flag_references(certification: &Certification, state: CertificationState, now: u64) {
+    let mut affected: BTreeMap<Principal, Vec<Affected>> = BTreeMap::new();
+
+    let mut product_ids = referencing(&PRODUCT_CERTIFICATIONS, &certification.id);
+    if let CertificationSubject::Product(product_id) = &certification.subject {
+        product_ids.insert(product_id.clone());
+    }
+    for product_id in product_ids {
+        let Some(mut product) = PRODUCTS.with(|products| products.borrow().get(&product_id)) else {
+            continue;
+        };
+        flag(&mut product.certification_alerts, certification, state, now);
+        let holder = product.manufacturer_id;
+        store_product(product);
+
+        let score = calculate_ethical_score(&product_id).ok();
+        affected.entry(holder).or_default().push((product_id, score));
+    }
+
+    let mut partner_ids = referencing(&PARTNER_CERTIFICATIONS, &certification.id);
+    if let CertificationSubject::Partner(partner_id) = &certification.subject {
+        partner_ids.insert(partner_id.clone());
+    }
+    for partner_id in partner_ids {
+        let Some(mut partner) = PARTNERS.with(|partners| partners.borrow().get(&partner_id)) else {
+            continue;
+        };
+        flag(&mut partner.certification_alerts, certification, state, now);
+        affected.entry(partner.manager_id).or_default().push((partner_id, None));
+        store_partner(partner);
+    }
+
+    for (recipient, affected) in affected {
+        notify(recipient, certification, state, affected, now);
+    }
+}
+
+fn referencing(index: &'static LocalKey<ReferenceIndex>, certification_id: &str) -> BTreeSet<String> {
+    index.with(|index| {
+        index.borrow()
+            .range(Key(certification_id.to_string(), String::new())..)
+            .take_while(|(Key(referenced_id, _), _)| referenced_id == certification_id)
+            .map(|(Key(_, record_id), _)| record_id)
+            .collect()
+    })
+}
+
+// Keeps PRODUCT_CERTIFICATIONS in sync with the product's references
+pub fn reindex_product_references(previous: Option<&Product>, product: &Product) {
+    let previous = previous.map_or(&[][..], |previous| &previous.certifications[..]);
+    reindex_references(&PRODUCT_CERTIFICATIONS, previous, &product.certifications, &product.id);
+}
+
+// Keeps PARTNER_CERTIFICATIONS in sync with the partner's references
+pub fn reindex_partner_references(previous: Option<&Partner>, partner: &Partner) {
+    let previous = previous.map_or(&[][..], |previous| &previous.certifications[..]);
+    reindex_references(&PARTNER_CERTIFICATIONS, previous, &partner.certifications, &partner.id);
+}
+
+fn reindex_references(index: &'static LocalKey<ReferenceIndex>, previous: &[String], current: &[String], record_id: &str) {
+    index.with(|index| {
+        let mut index = index.borrow_mut();
+        for certification_id in previous.iter().filter(|certification_id| !current.contains(certification_id)) {
+            index.remove(&Key(certification_id.clone(), record_id.to_string()));
+        }
+        for certification_id in current {
+            index.insert(Key(certification_id.clone(), record_id.to_string()), ());
+        }
+    });
+}
+
+// Keeps one alert per certification, replacing an earlier warning
+fn flag(alerts: &mut Option<Vec<CertificationAlert>>, certification: &Certification, state: CertificationState, now: u64) {
+    let alerts = alerts.get_or_insert_with(Vec::new);
+    alerts.retain(|alert| alert.certification_id != certification.id);
+    alerts.push(CertificationAlert {
+        certification_id: certification.id.clone(),
+        state,
+        valid_until: certification.valid_until,
+        flagged_at: now,
+    });
+}
+
+fn notify(
+    recipient: Principal,
+    certification: &Certification,
+    state: CertificationState,
+    affected: Vec<Affected>,
+    now: u64,
+) {
+    let listed = affected.iter()
+        .map(|(id, score)| match score {
+            Some(score) => format!("{} (ethical score now {:.1})", id, score),
+            None => id.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let message = match state {
+        CertificationState::Expiring => format!(
+            "{} certification {} expires soon. Affected: {}",
+            certification.standard, certification.id, listed
+        ),
+        CertificationState::Expired => format!(
+            "{} certification {} has expired. Affected: {}",
+            certification.standard, certification.id, listed
+        ),
+        CertificationState::Revoked => format!(
+            "{} certification {} has been revoked ({}). Affected: {}",
+            certification.standard,
+            certification.id,
+            certification.revocation_reason.as_deref().unwrap_or("no reason given"),
+            listed
+        ),
+    };
+
+    let id = next_id_sequence("notification");
+    let notification = Notification {
+        id,
+        recipient,
+        certification_id: certification.id.clone(),
+        state,
+        message,
+        affected: affected.into_iter().map(|(id, _)| id).collect(),
+        created_at: now,
+        read: false,
+    };
+    NOTIFICATIONS.with(|notifications| notifications.borrow_mut().insert((recipient, id), notification));
+}
@@ -0,0 +1,348 @@
+use sha2::{Digest, Sha256};
+use std::cmp::Ordering;
+
+pub type Hash = [u8; 32];
+
+// Label under which product head hashes are published in the certified tree
+const PRODUCTS_LABEL: &[u8] = b"products";
+
+// IC hash tree (see the "Certification" section of the IC interface spec).
+// Only the parts needed to publish and witness product heads are implemented.
+pub enum HashTree {
+    Empty,
+    Fork(Box<HashTree>, Box<HashTree>),
+    Labeled(Vec<u8>, Box<HashTree>),
+    Leaf(Vec<u8>),
+    Pruned(Hash),
+}
+
+impl HashTree {
+    pub fn digest(&self) -> Hash {
+        match self {
+            HashTree::Empty => domain_hash("ic-hashtree-empty", &[]),
+            HashTree::Fork(left, right) => {
+                domain_hash("ic-hashtree-fork", &[&left.digest(), &right.digest()])
+            }
+            HashTree::Labeled(label, tree) => {
+                domain_hash("ic-hashtree-labeled", &[label, &tree.digest()])
+            }
+            HashTree::Leaf(value) => domain_hash("ic-hashtree-leaf", &[value]),
+            HashTree::Pruned(hash) => *hash,
+        }
+    }
+
+    // CBOR encoding expected by agents, prefixed with the self-describing tag
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut bytes = vec![0xd9, 0xd9, 0xf7];
+        self.write_cbor(&mut bytes);
+        bytes
+    }
+
+    fn write_cbor(&self, out: &mut Vec<u8>) {
+        match self {
+            HashTree::Empty => {
+                out.push(0x81);
+                out.push(0);
+            }
+            HashTree::Fork(left, right) => {
+                out.push(0x83);
+                out.push(1);
+                left.write_cbor(out);
+                right.write_cbor(out);
+            }
+            HashTree::Labeled(label, tree) => {
+                out.push(0x83);
+                out.push(2);
+                write_cbor_bytes(out, label);
+                tree.write_cbor(out);
+            }
+            HashTree::Leaf(value) => {
+                out.push(0x82);
+                out.push(3);
+                write_cbor_bytes(out, value);
+            }
+            HashTree::Pruned(hash) => {
+                out.push(0x82);
+                out.push(4);
+                write_cbor_bytes(out, hash);
+            }
+        }
+    }
+}
+
+// Head hash of every product, published as `products/<product_id> -> head_hash`.
+// Lives on the heap and is rebuilt from stable memory after upgrades.
+//
+// Heads are kept in a treap ordered by product ID whose priorities are derived
+// from the ID, so the tree's shape depends only on the set of products. Every
+// node caches the hash of its subtree: recording a head rehashes the path to one
+// node, and the root hash is read without touching the rest of the tree.
+#[derive(Default)]
+pub struct CertifiedHeads {
+    root: Option<Box<HeadNode>>,
+}
+
+struct HeadNode {
+    product_id: String,
+    head_hash: String,
+    priority: Hash,
+    // Digest of `Labeled(product_id, Leaf(head_hash))`
+    leaf_digest: Hash,
+    subtree_digest: Hash,
+    left: Option<Box<HeadNode>>,
+    right: Option<Box<HeadNode>>,
+}
+
+impl CertifiedHeads {
+    pub fn insert(&mut self, product_id: String, head_hash: String) {
+        self.root = Some(insert_head(self.root.take(), product_id, head_hash));
+    }
+
+    pub fn root_hash(&self) -> Hash {
+        let products_digest = self.root.as_ref().map_or_else(|| HashTree::Empty.digest(), |root| root.subtree_digest);
+        domain_hash("ic-hashtree-labeled", &[PRODUCTS_LABEL, &products_digest])
+    }
+
+    // Tree with every branch pruned except the path to `product_id`
+    pub fn witness(&self, product_id: &str) -> HashTree {
+        let products = self.root.as_ref().map_or(HashTree::Empty, |root| witness_node(root, product_id));
+        HashTree::Labeled(PRODUCTS_LABEL.to_vec(), Box::new(products))
+    }
+}
+
+impl HeadNode {
+    fn new(product_id: String, head_hash: String) -> Box<Self> {
+        let mut node = Box::new(HeadNode {
+            priority: Sha256::digest(product_id.as_bytes()).into(),
+            product_id,
+            head_hash,
+            leaf_digest: [0; 32],
+            subtree_digest: [0; 32],
+            left: None,
+            right: None,
+        });
+        node.set_head(None);
+        node
+    }
+
+    fn labeled_leaf(&self) -> HashTree {
+        HashTree::Labeled(
+            self.product_id.as_bytes().to_vec(),
+            Box::new(HashTree::Leaf(self.head_hash.as_bytes().to_vec())),
+        )
+    }
+
+    fn set_head(&mut self, head_hash: Option<String>) {
+        if let Some(head_hash) = head_hash {
+            self.head_hash = head_hash;
+        }
+        self.leaf_digest = self.labeled_leaf().digest();
+        self.rehash();
+    }
+
+    // Same shape as `compose`, so witnesses reproduce the cached digests
+    fn rehash(&mut self) {
+        let fork = |left: &Hash, right: &Hash| domain_hash("ic-hashtree-fork", &[left, right]);
+        self.subtree_digest = match (&self.left, &self.right) {
+            (None, None) => self.leaf_digest,
+            (Some(left), None) => fork(&left.subtree_digest, &self.leaf_digest),
+            (None, Some(right)) => fork(&self.leaf_digest, &right.subtree_digest),
+            (Some(left), Some(right)) => {
+                fork(&left.subtree_digest, &fork(&self.leaf_digest, &right.subtree_digest))
+            }
+        };
+    }
+}
+
+fn insert_head(node: Option<Box<HeadNode>>, product_id: String, head_hash: String) -> Box<HeadNode> {
+    let Some(mut node) = node else {
+        return HeadNode::new(product_id, head_hash);
+    };
+
+    match product_id.cmp(&node.product_id) {
+        Ordering::Equal => node.set_head(Some(head_hash)),
+        Ordering::Less => {
+            let left = insert_head(node.left.take(), product_id, head_hash);
+            let rotate = left.priority > node.priority;
+            node.left = Some(left);
+            if rotate {
+                return rotate_right(node);
+            }
+            node.rehash();
+        }
+        Ordering::Greater => {
+            let right = insert_head(node.right.take(), product_id, head_hash);
+            let rotate = right.priority > node.priority;
+            node.right = Some(right);
+            if rotate {
+                return rotate_left(node);
+            }
+            node.rehash();
+        }
+    }
+    node
+}
+
+fn rotate_right(mut node: Box<HeadNode>) -> Box<HeadNode> {
+    let mut left = node.left.take().expect("rotated node has a left child");
+    node.left = left.right.take();
+    node.rehash();
+    left.right = Some(node);
+    left.rehash();
+    left
+}
+
+fn rotate_left(mut node: Box<HeadNode>) -> Box<HeadNode> {
+    let mut right = node.right.take().expect("rotated node has a right child");
+    node.right = right.left.take();
+    node.rehash();
+    right.left = Some(node);
+    right.rehash();
+    right
+}
+
+fn witness_node(node: &HeadNode, product_id: &str) -> HashTree {
+    let pruned = |child: &Option<Box<HeadNode>>| child.as_ref().map(|child| HashTree::Pruned(child.subtree_digest));
+    let descend = |child: &Option<Box<HeadNode>>| child.as_ref().map(|child| witness_node(child, product_id));
+
+    match product_id.cmp(node.product_id.as_str()) {
+        Ordering::Equal => compose(pruned(&node.left), node.labeled_leaf(), pruned(&node.right)),
+        Ordering::Less => compose(descend(&node.left), HashTree::Pruned(node.leaf_digest), pruned(&node.right)),
+        Ordering::Greater => compose(pruned(&node.left), HashTree::Pruned(node.leaf_digest), descend(&node.right)),
+    }
+}
+
+// Keeps labels in order left to right: left subtree, the node's own leaf, right subtree
+fn compose(left: Option<HashTree>, leaf: HashTree, right: Option<HashTree>) -> HashTree {
+    match (left, right) {
+        (None, None) => leaf,
+        (Some(left), None) => HashTree::Fork(Box::new(left), Box::new(leaf)),
+        (None, Some(right)) => HashTree::Fork(Box::new(leaf), Box::new(right)),
+        (Some(left), Some(right)) => HashTree::Fork(
+            Box::new(left),
+            Box::new(HashTree::Fork(Box::new(leaf), Box::new(right))),
+        ),
+    }
+}
+
+fn domain_hash(domain: &str, parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+fn write_cbor_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    let len = bytes.len();
+    if len < 24 {
+        out.push(0x40 | len as u8);
+    } else if len < 0x100 {
+        out.push(0x58);
+        out.push(len as u8);
+    } else if len < 0x10000 {
+        out.push(0x59);
+        out.extend_from_slice(&(len as u16).to_be_bytes());
+    } else {
+        out.push(0x5a);
+        out.extend_from_slice(&(len as u32).to_be_bytes());
+    }
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::to_hex;
+
+    fn leaf(value: &str) -> HashTree {
+        HashTree::Leaf(value.as_bytes().to_vec())
+    }
+
+    fn labeled(label: &str, tree: HashTree) -> HashTree {
+        HashTree::Labeled(label.as_bytes().to_vec(), Box::new(tree))
+    }
+
+    fn fork(left: HashTree, right: HashTree) -> HashTree {
+        HashTree::Fork(Box::new(left), Box::new(right))
+    }
+
+    // Value stored under `path`, if the witness reveals it
+    fn lookup<'a>(tree: &'a HashTree, path: &[&str]) -> Option<&'a [u8]> {
+        match (tree, path) {
+            (HashTree::Leaf(value), []) => Some(value),
+            (HashTree::Labeled(label, subtree), [first, rest @ ..]) if label == first.as_bytes() => lookup(subtree, rest),
+            (HashTree::Fork(left, right), _) => lookup(left, path).or_else(|| lookup(right, path)),
+            _ => None,
+        }
+    }
+
+    fn heads(product_ids: impl IntoIterator<Item = usize>) -> CertifiedHeads {
+        let mut heads = CertifiedHeads::default();
+        for i in product_ids {
+            heads.insert(format!("CT-2024-{:06}", i), format!("head-{}", i));
+        }
+        heads
+    }
+
+    #[test]
+    fn digest_matches_interface_spec_example() {
+        let tree = fork(
+            fork(
+                labeled("a", fork(fork(labeled("x", leaf("hello")), HashTree::Empty), labeled("y", leaf("world")))),
+                labeled("b", leaf("good")),
+            ),
+            fork(labeled("c", HashTree::Empty), labeled("d", leaf("morning"))),
+        );
+
+        assert_eq!(to_hex(&tree.digest()), "eb5c5b2195e62d996b84c9bcc8259d19a83786a2f59e0878cec84c811f669aa0");
+    }
+
+    #[test]
+    fn cbor_matches_reference_encoding() {
+        let tree = fork(labeled("a", leaf("hello")), fork(HashTree::Pruned([0x11; 32]), HashTree::Empty));
+
+        assert_eq!(
+            to_hex(&tree.to_cbor()),
+            format!("d9d9f783018302416182034568656c6c6f830182045820{}8100", "11".repeat(32))
+        );
+    }
+
+    #[test]
+    fn empty_heads_certify_an_empty_products_subtree() {
+        let heads = CertifiedHeads::default();
+
+        assert_eq!(heads.root_hash(), labeled("products", HashTree::Empty).digest());
+        assert_eq!(heads.witness("CT-2024-000001").digest(), heads.root_hash());
+    }
+
+    #[test]
+    fn witness_reveals_head_and_matches_root() {
+        let heads = heads((0..100).map(|i| i * 37 % 100));
+
+        for i in 0..100 {
+            let product_id = format!("CT-2024-{:06}", i);
+            let witness = heads.witness(&product_id);
+            assert_eq!(witness.digest(), heads.root_hash());
+            assert_eq!(lookup(&witness, &["products", &product_id]), Some(format!("head-{}", i).as_bytes()));
+            assert_eq!(lookup(&witness, &["products", "CT-2024-000100"]), None);
+        }
+    }
+
+    #[test]
+    fn root_depends_on_heads_not_insertion_order() {
+        let ascending = heads(0..50);
+        let descending = heads((0..50).rev());
+        assert_eq!(ascending.root_hash(), descending.root_hash());
+
+        let mut updated = heads(0..50);
+        updated.insert("CT-2024-000007".to_string(), "head-7b".to_string());
+        assert_ne!(updated.root_hash(), ascending.root_hash());
+
+        let mut rebuilt = heads((0..50).filter(|&i| i != 7));
+        rebuilt.insert("CT-2024-000007".to_string(), "head-7b".to_string());
+        assert_eq!(updated.root_hash(), rebuilt.root_hash());
+    }
+}
@@ -0,0 +1,162 @@
+use crate::types::*;
+use crate::certifications::certification_label;
+use crate::labels::{product_label_svg, product_qr_svg};
+use crate::utils::{escape_html, format_date};
+use crate::load_product_with_history;
+
+const HTML_CONTENT_TYPE: &str = "text/html; charset=utf-8";
+const JSON_CONTENT_TYPE: &str = "application/json";
+const SVG_CONTENT_TYPE: &str = "image/svg+xml";
+
+// Product pages change whenever an event is added, so they are only cached briefly
+const FOUND_CACHE_CONTROL: &str = "public, max-age=60";
+const ERROR_CACHE_CONTROL: &str = "no-store";
+
+pub fn handle_http_request(request: &HttpRequest) -> HttpResponse {
+    if request.method != "GET" && request.method != "HEAD" {
+        return text_response(405, "Method not allowed");
+    }
+
+    let path = request.url.split(['?', '#']).next().unwrap_or_default();
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+    let mut response = match segments.as_slice() {
+        ["product", product_id] => product_page(product_id),
+        ["product", product_id, "qr.svg"] => product_svg(product_id, |product| product_qr_svg(&product.id)),
+        ["product", product_id, "label.svg"] => product_svg(product_id, product_label_svg),
+        ["api", "product", product_id] => product_json(product_id),
+        _ => text_response(404, "Not found"),
+    };
+
+    if request.method == "HEAD" {
+        response.body.clear();
+    }
+    response
+}
+
+fn product_page(product_id: &str) -> HttpResponse {
+    match load_product_with_history(product_id, &ic_cdk::caller()) {
+        Ok(product) => response(200, HTML_CONTENT_TYPE, FOUND_CACHE_CONTROL, render_product_page(&product).into_bytes()),
+        Err(error) => {
+            let body = render_page("Product verification", &format!("<p class=\"error\">{}</p>", escape_html(&error)));
+            response(error_status(&error), HTML_CONTENT_TYPE, ERROR_CACHE_CONTROL, body.into_bytes())
+        }
+    }
+}
+
+fn product_json(product_id: &str) -> HttpResponse {
+    match load_product_with_history(product_id, &ic_cdk::caller()) {
+        Ok(product) => match serde_json::to_vec(&product) {
+            Ok(body) => response(200, JSON_CONTENT_TYPE, FOUND_CACHE_CONTROL, body),
+            Err(error) => json_error(500, &format!("Failed to encode product: {}", error)),
+        },
+        Err(error) => json_error(error_status(&error), &error),
+    }
+}
+
+fn product_svg(product_id: &str, render: impl Fn(&Product) -> Result<String, String>) -> HttpResponse {
+    match load_product_with_history(product_id, &ic_cdk::caller()).and_then(|details| render(&details.product)) {
+        // Labels only change when the product record does, so they can be cached longer
+        Ok(svg) => response(200, SVG_CONTENT_TYPE, "public, max-age=3600", svg.into_bytes()),
+        Err(error) => text_response(error_status(&error), &error),
+    }
+}
+
+// Maps the canister's error strings onto HTTP statuses
+fn error_status(error: &str) -> u16 {
+    if error.ends_with("not found") {
+        404
+    } else {
+        500
+    }
+}
+
+fn render_product_page(details: &ProductWithHistory) -> String {
+    let product = &details.product;
+    let mut content = format!(
+        "<h1>{}</h1>\
+         <dl>\
+         <dt>Product ID</dt><dd>{}</dd>\
+         <dt>Manufacturer</dt><dd>{}</dd>\
+         <dt>Category</dt><dd>{}</dd>\
+         <dt>Batch</dt><dd>{}</dd>\
+         <dt>Produced</dt><dd>{}</dd>\
+         <dt>Status</dt><dd>{:?}</dd>\
+         <dt>Current location</dt><dd>{}</dd>\
+         <dt>Ethical score</dt><dd>{:.0} / 100</dd>\
+         <dt>Certifications</dt><dd>{}</dd>\
+         <dt>History head hash</dt><dd><code>{}</code></dd>\
+         </dl>",
+        escape_html(&product.name),
+        escape_html(&product.id),
+        escape_html(&product.manufacturer),
+        escape_html(&product.category),
+        escape_html(product.batch_number.as_deref().unwrap_or("-")),
+        format_date(product.production_date),
+        product.current_status,
+        escape_html(&product.current_location),
+        details.ethical_score,
+        escape_html(&product.certifications.iter().map(|certification| certification_label(certification)).collect::<Vec<_>>().join(", ")),
+        escape_html(details.head_hash.as_deref().unwrap_or("-")),
+    );
+
+    content.push_str("<h2>Supply chain history</h2><ol class=\"timeline\">");
+    for event in &details.supply_chain_events {
+        let note = match (&event.kind, &event.superseded_by) {
+            (_, Some(amendment_id)) => format!("<br><em>Superseded by {}</em>", escape_html(amendment_id)),
+            (Some(EventKind::Amendment { amends, reason }), None) => {
+                format!("<br><em>Corrects {}: {}</em>", escape_html(amends), escape_html(reason))
+            }
+            _ => String::new(),
+        };
+        content.push_str(&format!(
+            "<li><strong>{:?}</strong> &middot; {:?} &middot; {}<br>{} &mdash; {}<br><small>{}</small>{}</li>",
+            event.stage,
+            event.status,
+            format_date(event.timestamp),
+            escape_html(&event.location),
+            escape_html(&event.actor),
+            escape_html(&event.details),
+            note,
+        ));
+    }
+    content.push_str("</ol>");
+
+    render_page(&product.name, &content)
+}
+
+fn render_page(title: &str, content: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\
+         <html lang=\"en\"><head><meta charset=\"utf-8\">\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\
+         <title>{}</title>\
+         <style>body{{font-family:sans-serif;max-width:40rem;margin:2rem auto;padding:0 1rem}}\
+         dt{{font-weight:bold}}dd{{margin:0 0 .5rem}}code{{word-break:break-all}}\
+         .timeline li{{margin-bottom:.75rem}}.error{{color:#b00020}}</style>\
+         </head><body>{}</body></html>",
+        escape_html(title),
+        content
+    )
+}
+
+fn json_error(status_code: u16, error: &str) -> HttpResponse {
+    let body = serde_json::json!({ "error": error }).to_string();
+    response(status_code, JSON_CONTENT_TYPE, ERROR_CACHE_CONTROL, body.into_bytes())
+}
+
+fn text_response(status_code: u16, message: &str) -> HttpResponse {
+    response(status_code, "text/plain; charset=utf-8", ERROR_CACHE_CONTROL, message.as_bytes().to_vec())
+}
+
+fn response(status_code: u16, content_type: &str, cache_control: &str, body: Vec<u8>) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            ("Content-Type".to_string(), content_type.to_string()),
+            ("Cache-Control".to_string(), cache_control.to_string()),
+            ("Content-Length".to_string(), body.len().to_string()),
+        ],
+        body,
+    }
+}
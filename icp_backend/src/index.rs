@@ -0,0 +1,136 @@
+use crate::types::{Product, ProductSearchQuery, ProductStatus};
+use crate::storage::Key;
+use crate::PRODUCT_INDEX;
+
+// Secondary indexes over products live in a single map keyed by
+// (index key, product_id). Index keys are "<field>:<value>"; production dates
+// are zero-padded so that lexicographic order matches numeric order.
+const MANUFACTURER_ID: &str = "manufacturer_id";
+const ORGANISATION: &str = "organisation";
+const CATEGORY: &str = "category";
+const STATUS: &str = "status";
+const BATCH: &str = "batch";
+const PRODUCTION_DATE: &str = "production_date";
+
+// Candidate counts stop here; indexes at least this broad are all treated as
+// equally unselective, so no single probe walks a whole popular key
+const SELECTIVITY_PROBE_LIMIT: usize = 1_000;
+
+pub fn product_index_keys(product: &Product) -> Vec<String> {
+    let mut keys = vec![
+        index_key(MANUFACTURER_ID, &product.manufacturer_id.to_text()),
+        index_key(CATEGORY, &product.category),
+        status_key(&product.current_status),
+        production_date_key(product.production_date),
+    ];
+    if let Some(batch_number) = &product.batch_number {
+        keys.push(index_key(BATCH, batch_number));
+    }
+    if let Some(organisation_id) = &product.organisation_id {
+        keys.push(index_key(ORGANISATION, organisation_id));
+    }
+    keys
+}
+
+// Keeps the index entries of a product in sync with its new version
+pub fn reindex_product(previous: Option<&Product>, product: &Product) {
+    let new_keys = product_index_keys(product);
+    let old_keys = previous.map(product_index_keys).unwrap_or_default();
+
+    PRODUCT_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for key in old_keys.iter().filter(|key| !new_keys.contains(key)) {
+            index.remove(&Key(key.clone(), product.id.clone()));
+        }
+        for key in new_keys {
+            index.insert(Key(key, product.id.clone()), ());
+        }
+    });
+}
+
+// A contiguous run of index keys that a query constraint maps to
+pub struct IndexScan {
+    from: String,
+    to: String,
+}
+
+impl IndexScan {
+    fn exact(key: String) -> Self {
+        IndexScan { from: key.clone(), to: key }
+    }
+
+    // Number of matching products, counting no further than `limit`
+    fn count_up_to(&self, limit: usize) -> usize {
+        PRODUCT_INDEX.with(|index| {
+            index.borrow()
+                .range(Key(self.from.clone(), String::new())..)
+                .take_while(|(Key(key, _), _)| *key <= self.to)
+                .take(limit)
+                .count()
+        })
+    }
+
+    pub fn product_ids(&self) -> Vec<String> {
+        PRODUCT_INDEX.with(|index| {
+            index.borrow()
+                .range(Key(self.from.clone(), String::new())..)
+                .take_while(|(Key(key, _), _)| *key <= self.to)
+                .map(|(Key(_, product_id), _)| product_id)
+                .collect()
+        })
+    }
+}
+
+// IDs of every product carrying the batch number
+pub fn batch_product_ids(batch_number: &str) -> Vec<String> {
+    IndexScan::exact(index_key(BATCH, batch_number)).product_ids()
+}
+
+// Picks the index that yields the fewest candidates for the query's exact-match
+// and range constraints. None means no indexed field is constrained.
+pub fn most_selective_scan(query: &ProductSearchQuery) -> Option<IndexScan> {
+    let mut scans = Vec::new();
+    if let Some(manufacturer_id) = &query.manufacturer_id {
+        scans.push(IndexScan::exact(index_key(MANUFACTURER_ID, &manufacturer_id.to_text())));
+    }
+    if let Some(organisation_id) = &query.organisation_id {
+        scans.push(IndexScan::exact(index_key(ORGANISATION, organisation_id)));
+    }
+    if let Some(category) = &query.category {
+        scans.push(IndexScan::exact(index_key(CATEGORY, category)));
+    }
+    if let Some(status) = &query.status {
+        scans.push(IndexScan::exact(status_key(status)));
+    }
+    if let Some(batch_number) = &query.batch_number {
+        scans.push(IndexScan::exact(index_key(BATCH, batch_number)));
+    }
+    if query.production_date_from.is_some() || query.production_date_to.is_some() {
+        scans.push(IndexScan {
+            from: production_date_key(query.production_date_from.unwrap_or(0)),
+            to: production_date_key(query.production_date_to.unwrap_or(u64::MAX)),
+        });
+    }
+
+    let mut best: Option<(usize, IndexScan)> = None;
+    for scan in scans {
+        let limit = best.as_ref().map_or(SELECTIVITY_PROBE_LIMIT, |(count, _)| *count);
+        let count = scan.count_up_to(limit);
+        if best.is_none() || count < limit {
+            best = Some((count, scan));
+        }
+    }
+    best.map(|(_, scan)| scan)
+}
+
+fn index_key(field: &str, value: &str) -> String {
+    format!("{}:{}", field, value)
+}
+
+fn status_key(status: &ProductStatus) -> String {
+    index_key(STATUS, &format!("{:?}", status))
+}
+
+fn production_date_key(production_date: u64) -> String {
+    index_key(PRODUCTION_DATE, &format!("{:020}", production_date))
+}
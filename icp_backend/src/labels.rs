@@ -0,0 +1,57 @@
+use crate::qrcode::QrCode;
+use crate::types::Product;
+use crate::utils::escape_html;
+use crate::PUBLIC_BASE_URL;
+
+// Longer product names are cut so they fit on one line of the label
+const MAX_LABEL_NAME_CHARS: usize = 36;
+
+// Public page a product's QR code points to. Defaults to the canister's raw
+// domain because the HTTP responses are not certified.
+pub fn verification_url(product_id: &str) -> String {
+    let base_url = PUBLIC_BASE_URL.with(|url| url.borrow().get().clone());
+    let base_url = if base_url.is_empty() {
+        format!("https://{}.raw.icp0.io", ic_cdk::id())
+    } else {
+        base_url
+    };
+    format!("{}/product/{}", base_url.trim_end_matches('/'), product_id)
+}
+
+pub fn product_qr_svg(product_id: &str) -> Result<String, String> {
+    QrCode::encode(verification_url(product_id).as_bytes()).map(|qr| qr.to_svg())
+}
+
+// Printable 600x240 label: QR code on the left, product details on the right
+pub fn product_label_svg(product: &Product) -> Result<String, String> {
+    let url = verification_url(&product.id);
+    let qr = QrCode::encode(url.as_bytes())?;
+    let qr_scale = 200.0 / qr.width() as f64;
+
+    let name: String = if product.name.chars().count() > MAX_LABEL_NAME_CHARS {
+        let truncated: String = product.name.chars().take(MAX_LABEL_NAME_CHARS - 1).collect();
+        format!("{}\u{2026}", truncated)
+    } else {
+        product.name.clone()
+    };
+
+    Ok(format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"600\" height=\"240\" viewBox=\"0 0 600 240\">\
+         <rect width=\"600\" height=\"240\" fill=\"#ffffff\" stroke=\"#000000\" stroke-width=\"2\"/>\
+         <g transform=\"translate(20 20) scale({qr_scale})\" shape-rendering=\"crispEdges\">\
+         <path d=\"{qr_path}\" fill=\"#000000\"/></g>\
+         <g font-family=\"Helvetica, Arial, sans-serif\" fill=\"#000000\">\
+         <text x=\"240\" y=\"60\" font-size=\"22\" font-weight=\"bold\">{name}</text>\
+         <text x=\"240\" y=\"100\" font-size=\"16\">Batch: {batch}</text>\
+         <text x=\"240\" y=\"130\" font-size=\"16\" font-family=\"monospace\">ID: {id}</text>\
+         <text x=\"240\" y=\"175\" font-size=\"14\">Scan to verify provenance</text>\
+         <text x=\"240\" y=\"200\" font-size=\"9\">{url}</text>\
+         </g></svg>",
+        qr_scale = qr_scale,
+        qr_path = qr.svg_path(),
+        name = escape_html(&name),
+        batch = escape_html(product.batch_number.as_deref().unwrap_or("-")),
+        id = escape_html(&product.id),
+        url = escape_html(&url),
+    ))
+}
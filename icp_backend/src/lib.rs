@@ -1,17 +1,38 @@
 use candid::Principal;
+use ic_cdk::api::management_canister::main::raw_rand;
 use ic_cdk::api::time;
 use ic_cdk_macros::*;
 use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
-use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap};
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell};
 use std::cell::RefCell;
+use std::cmp::Reverse;
+use std::ops::{Bound, RangeInclusive};
+use std::time::Duration;
 
 mod types;
 mod storage;
 mod utils;
+mod migrations;
+mod certified_data;
+mod http;
+mod qrcode;
+mod labels;
+mod index;
+mod pagination;
+mod access;
+mod reputation;
+mod scoring;
+mod text_index;
+mod certifications;
 
 use types::*;
 use storage::*;
 use utils::*;
+use migrations::*;
+use access::{AccessLevel, Viewer};
+use certifications::CertificationContext;
+use certified_data::CertifiedHeads;
+use pagination::{collect_page, decode_cursor, decode_sequence_cursor, page_size};
 
 type Memory = VirtualMemory<DefaultMemoryImpl>;
 
@@ -31,17 +52,288 @@ thread_local! {
         )
     );
 
-    static SUPPLY_CHAIN_EVENTS: RefCell<StableBTreeMap<String, SupplyChainEventList, Memory>> = RefCell::new(
+    // Pre-v2 layout holding each product's whole history in one value; drained by the
+    // storage migration and kept only so older stable memory can still be read.
+    static LEGACY_SUPPLY_CHAIN_EVENTS: RefCell<StableBTreeMap<String, SupplyChainEventList, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(2))),
         )
     );
 
-    static PARTNERS: RefCell<StableBTreeMap<Principal, Partner, Memory>> = RefCell::new(
+    // Pre-v8 partners keyed by their registering principal; emptied by the migration
+    static LEGACY_PARTNERS: RefCell<StableBTreeMap<Principal, PartnerV1, Memory>> = RefCell::new(
         StableBTreeMap::init(
             MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(3))),
         )
     );
+
+    static BOOTSTRAP_ADMINS: RefCell<StableBTreeMap<Principal, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(4))),
+        )
+    );
+
+    static STORAGE_VERSION: RefCell<StableCell<u32, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(5))),
+            0,
+        ).expect("Failed to initialize storage version")
+    );
+
+    // Events keyed by (product_id, sequence) so histories grow one entry at a time
+    static SUPPLY_CHAIN_EVENTS: RefCell<StableBTreeMap<Key<String, u64>, SupplyChainEvent, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(6))),
+        )
+    );
+
+    // Monotonic counters backing ID generation, keyed by ID namespace
+    static ID_COUNTERS: RefCell<StableBTreeMap<String, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(7))),
+        )
+    );
+
+    // Random salt mixed into generated IDs; empty until seeded from raw_rand
+    static ID_SALT: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(8))),
+            Vec::new(),
+        ).expect("Failed to initialize ID salt")
+    );
+
+    // Event ID -> (product_id, sequence) in SUPPLY_CHAIN_EVENTS
+    static EVENT_IDS: RefCell<StableBTreeMap<String, Key<String, u64>, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(9))),
+        )
+    );
+
+    static TRANSITION_RULES: RefCell<StableCell<StageTransitionRules, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(10))),
+            default_transition_rules(),
+        ).expect("Failed to initialize stage transition rules")
+    );
+
+    static RECALLS: RefCell<StableBTreeMap<String, Recall, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(11))),
+        )
+    );
+
+    // Base URL encoded in product QR codes; empty means the canister's own raw domain
+    static PUBLIC_BASE_URL: RefCell<StableCell<String, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(12))),
+            String::new(),
+        ).expect("Failed to initialize public base URL")
+    );
+
+    // Secondary product indexes keyed by (index key, product_id); see index.rs
+    static PRODUCT_INDEX: RefCell<StableBTreeMap<Key<String, String>, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(13))),
+        )
+    );
+
+    // Full-text token index keyed by (token, product_id); see text_index.rs
+    static TEXT_INDEX: RefCell<StableBTreeMap<Key<String, String>, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(14))),
+        )
+    );
+
+    // Product change log keyed by (product_id, sequence)
+    static PRODUCT_CHANGES: RefCell<StableBTreeMap<Key<String, u64>, ProductChange, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(15))),
+        )
+    );
+
+    // Amended event_id -> ID of the amendment event that supersedes it
+    static EVENT_AMENDMENTS: RefCell<StableBTreeMap<String, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(16))),
+        )
+    );
+
+    // Visibility and grants per product; products without an entry are public
+    static PRODUCT_ACCESS: RefCell<StableBTreeMap<String, ProductAccess, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(17))),
+        )
+    );
+
+    static ORGANISATIONS: RefCell<StableBTreeMap<String, Organisation, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(18))),
+        )
+    );
+
+    // Normalised organisation name -> organisation_id; keeps names unique
+    static ORGANISATION_NAMES: RefCell<StableBTreeMap<String, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(19))),
+        )
+    );
+
+    static PARTNERS: RefCell<StableBTreeMap<String, Partner, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(20))),
+        )
+    );
+
+    // Reputation counters per partner_id; see reputation.rs
+    static PARTNER_REPUTATION: RefCell<StableBTreeMap<String, ReputationStats, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(21))),
+        )
+    );
+
+    // Principals that recorded an event, per (product_id, actor_id)
+    static EVENT_ACTORS: RefCell<StableBTreeMap<Key<String, Principal>, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(34))),
+        )
+    );
+
+    // Partner IDs per (manager_id, partner_id); maintained by store_partner
+    static PARTNERS_BY_MANAGER: RefCell<StableBTreeMap<Key<Principal, String>, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(31))),
+        )
+    );
+
+    // ID of the latest stage update per product, so the next one can settle its
+    // delivery estimate; see reputation.rs
+    static LAST_STAGE_UPDATES: RefCell<StableBTreeMap<String, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(32))),
+        )
+    );
+
+    // Partners credited with a stage update per (product_id, partner_id)
+    static PRODUCT_HANDLERS: RefCell<StableBTreeMap<Key<String, String>, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(33))),
+        )
+    );
+
+    // Latest rating per (partner_id, rater)
+    static PARTNER_RATINGS: RefCell<StableBTreeMap<Key<String, Principal>, u8, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(22))),
+        )
+    );
+
+    // Ethical scoring policies by version. Version 1 is the built-in default and
+    // is not stored.
+    static SCORING_POLICIES: RefCell<StableBTreeMap<u32, ScoringPolicy, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(23))),
+        )
+    );
+
+    static CERTIFICATIONS: RefCell<StableBTreeMap<String, Certification, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(24))),
+        )
+    );
+
+    // Products and partners referencing each certification, keyed by
+    // (certification_id, product_id) and (certification_id, partner_id)
+    static PRODUCT_CERTIFICATIONS: RefCell<StableBTreeMap<Key<String, String>, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(35))),
+        )
+    );
+
+    static PARTNER_CERTIFICATIONS: RefCell<StableBTreeMap<Key<String, String>, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(36))),
+        )
+    );
+
+    // Certification the running scan pass stopped after; empty between passes
+    static CERTIFICATION_SCAN_CURSOR: RefCell<StableCell<String, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(37))),
+            String::new(),
+        ).expect("Failed to initialize certification scan cursor")
+    );
+
+    // Notifications per (recipient, notification id)
+    static NOTIFICATIONS: RefCell<StableBTreeMap<(Principal, u64), Notification, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(25))),
+        )
+    );
+
+    static LOTS: RefCell<StableBTreeMap<String, Lot, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(26))),
+        )
+    );
+
+    // Lot genealogy keyed by (parent_id, child_id); parents are found through
+    // Lot::parent_ids
+    static LOT_EDGES: RefCell<StableBTreeMap<Key<String, String>, LotEdge, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(27))),
+        )
+    );
+
+    // Active lots per product keyed by (product_id, lot_id); maintained by store_lot
+    static PRODUCT_LOTS: RefCell<StableBTreeMap<Key<String, String>, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(30))),
+        )
+    );
+
+    static FACILITIES: RefCell<StableBTreeMap<String, Facility, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(29))),
+        )
+    );
+
+    // Tokens from fields hidden in redacted views, kept apart from TEXT_INDEX so
+    // they only rank products the searcher has full access to
+    static RESTRICTED_TEXT_INDEX: RefCell<StableBTreeMap<Key<String, String>, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MemoryId::new(28))),
+        )
+    );
+
+    // Heap-only; rebuilt from SUPPLY_CHAIN_EVENTS in post_upgrade
+    static CERTIFIED_HEADS: RefCell<CertifiedHeads> = RefCell::new(CertifiedHeads::default());
+}
+
+// Lifecycle Functions
+#[init]
+fn init(args: Option<InitArgs>) {
+    set_storage_version(CURRENT_STORAGE_VERSION);
+    publish_lapsed_certification_policy();
+    rebuild_certified_heads();
+    schedule_id_salt_seeding();
+    schedule_certification_scan();
+
+    if let Some(args) = args {
+        add_bootstrap_admins(args.admins);
+    }
+}
+
+#[post_upgrade]
+fn post_upgrade(args: Option<InitArgs>) {
+    run_migrations();
+    rebuild_certified_heads();
+    schedule_id_salt_seeding();
+    // Timers do not survive upgrades
+    schedule_certification_scan();
+
+    if let Some(args) = args {
+        add_bootstrap_admins(args.admins);
+    }
 }
 
 // User Management Functions
@@ -53,7 +345,19 @@ fn register_user(user_data: UserRegistration) -> Result<User, String> {
         return Err("Anonymous users cannot register".to_string());
     }
 
-    let permissions = get_default_permissions(&user_data.role);
+    if USERS.with(|users| users.borrow().contains_key(&caller)) {
+        return Err("User already registered".to_string());
+    }
+
+    // Roles are never self-assigned: bootstrap admins become Admin, everyone
+    // else starts as Consumer with the requested role awaiting approval.
+    let (role, pending_role) = if is_bootstrap_admin(&caller) {
+        (UserRole::Admin, None)
+    } else if user_data.role == UserRole::Consumer {
+        (UserRole::Consumer, None)
+    } else {
+        (UserRole::Consumer, Some(user_data.role))
+    };
 
     let user = User {
         id: caller,
@@ -61,10 +365,12 @@ fn register_user(user_data: UserRegistration) -> Result<User, String> {
         first_name: user_data.first_name,
         last_name: user_data.last_name,
         company: user_data.company,
-        role: user_data.role,
+        role,
         created_at: time(),
         is_verified: false,
-        permissions,
+        permissions: get_default_permissions(&role),
+        pending_role,
+        organisation_id: None,
     };
 
     USERS.with(|users| {
@@ -105,6 +411,196 @@ fn update_user_verification(user_id: Principal, verified: bool) -> Result<(), St
     })
 }
 
+#[update]
+fn assign_role(user_id: Principal, role: UserRole) -> Result<User, String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller)? {
+        return Err("Unauthorized: Admin access required".to_string());
+    }
+
+    let mut user = get_user_by_principal(&user_id)?;
+    set_user_role(&mut user, role);
+
+    USERS.with(|users| {
+        users.borrow_mut().insert(user_id, user.clone());
+    });
+
+    Ok(user)
+}
+
+#[update]
+fn request_role(role: UserRole) -> Result<User, String> {
+    let caller = ic_cdk::caller();
+    let mut user = get_user_by_principal(&caller)?;
+
+    if user.role == role {
+        return Err("User already has the requested role".to_string());
+    }
+
+    user.pending_role = Some(role);
+
+    USERS.with(|users| {
+        users.borrow_mut().insert(caller, user.clone());
+    });
+
+    Ok(user)
+}
+
+#[update]
+fn approve_role_request(user_id: Principal, approved: bool) -> Result<User, String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller)? {
+        return Err("Unauthorized: Admin access required".to_string());
+    }
+
+    let mut user = get_user_by_principal(&user_id)?;
+    let requested_role = user.pending_role
+        .ok_or_else(|| "No pending role request".to_string())?;
+
+    if approved {
+        set_user_role(&mut user, requested_role);
+    } else {
+        user.pending_role = None;
+    }
+
+    USERS.with(|users| {
+        users.borrow_mut().insert(user_id, user.clone());
+    });
+
+    Ok(user)
+}
+
+#[query]
+fn get_pending_role_requests(page: Option<PageRequest>) -> Result<Page<User>, String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller)? {
+        return Err("Unauthorized: Admin access required".to_string());
+    }
+
+    list_users_page(page, |user| user.pending_role.is_some())
+}
+
+#[query]
+fn list_users(page: Option<PageRequest>) -> Result<Page<User>, String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller)? {
+        return Err("Unauthorized: Admin access required".to_string());
+    }
+
+    list_users_page(page, |_| true)
+}
+
+// Organisation Management Functions
+#[update]
+fn create_organisation(name: String) -> Result<Organisation, String> {
+    let caller = ic_cdk::caller();
+    let mut user = get_user_by_principal(&caller)?;
+
+    if user.organisation_id.is_some() {
+        return Err("User already belongs to an organisation".to_string());
+    }
+
+    let normalized_name = normalize_company_name(&name);
+    if normalized_name.is_empty() {
+        return Err("Organisation name cannot be empty".to_string());
+    }
+
+    if let Some(existing_id) = ORGANISATION_NAMES.with(|names| names.borrow().get(&normalized_name)) {
+        return Err(format!("Organisation already exists as {}", existing_id));
+    }
+
+    let current_time = time();
+    let organisation = Organisation {
+        id: format!("ORG-{:06}", next_id_sequence("organisation")),
+        name: name.trim().to_string(),
+        members: vec![OrganisationMember {
+            user_id: caller,
+            role: OrganisationRole::Owner,
+            joined_at: current_time,
+        }],
+        created_at: current_time,
+    };
+
+    store_organisation(organisation.clone());
+    user.organisation_id = Some(organisation.id.clone());
+    USERS.with(|users| users.borrow_mut().insert(caller, user));
+
+    Ok(organisation)
+}
+
+#[update]
+fn add_organisation_member(organisation_id: String, user_id: Principal, role: OrganisationRole) -> Result<Organisation, String> {
+    let caller = ic_cdk::caller();
+    let mut organisation = get_organisation_by_id(&organisation_id)?;
+    authorize_membership_change(&organisation, &caller, role)?;
+
+    let mut user = get_user_by_principal(&user_id)?;
+    if user.organisation_id.is_some() {
+        return Err("User already belongs to an organisation".to_string());
+    }
+
+    organisation.members.push(OrganisationMember {
+        user_id,
+        role,
+        joined_at: time(),
+    });
+    store_organisation(organisation.clone());
+    user.organisation_id = Some(organisation_id);
+    USERS.with(|users| users.borrow_mut().insert(user_id, user));
+
+    Ok(organisation)
+}
+
+#[update]
+fn remove_organisation_member(organisation_id: String, user_id: Principal) -> Result<Organisation, String> {
+    let caller = ic_cdk::caller();
+    let mut organisation = get_organisation_by_id(&organisation_id)?;
+
+    let member_role = organisation_role(&organisation, &user_id)
+        .ok_or_else(|| "User is not a member of this organisation".to_string())?;
+    authorize_membership_change(&organisation, &caller, member_role)?;
+
+    let remaining_owners = organisation.members.iter()
+        .filter(|member| member.role == OrganisationRole::Owner && member.user_id != user_id)
+        .count();
+    if member_role == OrganisationRole::Owner && remaining_owners == 0 {
+        return Err("Cannot remove the last owner of an organisation".to_string());
+    }
+
+    organisation.members.retain(|member| member.user_id != user_id);
+    store_organisation(organisation.clone());
+    if let Ok(mut user) = get_user_by_principal(&user_id) {
+        user.organisation_id = None;
+        USERS.with(|users| users.borrow_mut().insert(user_id, user));
+    }
+
+    Ok(organisation)
+}
+
+#[query]
+fn get_organisation(organisation_id: String) -> Result<Organisation, String> {
+    get_organisation_by_id(&organisation_id)
+}
+
+#[query]
+fn list_organisations(page: Option<PageRequest>) -> Result<Page<Organisation>, String> {
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let start = match page.cursor.as_deref().map(decode_cursor).transpose()? {
+        Some(organisation_id) => Bound::Excluded(organisation_id),
+        None => Bound::Unbounded,
+    };
+
+    Ok(ORGANISATIONS.with(|organisations| {
+        let organisations = organisations.borrow();
+        let entries = organisations.range((start, Bound::Unbounded));
+        collect_page(entries, page_size(page.limit), Some(organisations.len()), |organisation_id| organisation_id.clone())
+    }))
+}
+
 // Product Management Functions
 #[update]
 fn register_product(product_data: ProductRegistration) -> Result<String, String> {
@@ -116,15 +612,33 @@ fn register_product(product_data: ProductRegistration) -> Result<String, String>
         return Err("Unauthorized: Cannot register products".to_string());
     }
 
-    let product_id = generate_product_id(&product_data.category);
     let current_time = time();
 
+    // Products of organisation members belong to the organisation
+    let organisation = user.organisation_id.as_deref()
+        .map(get_organisation_by_id)
+        .transpose()?;
+
+    // The product has no ID yet, so only batch, partner and facility
+    // certifications can be referenced at registration
+    certifications::validate_references(&product_data.certifications, &CertificationContext {
+        product_id: None,
+        batch_number: product_data.batch_number.as_deref(),
+        manufacturer_id: caller,
+        organisation_id: organisation.as_ref().map(|organisation| organisation.id.as_str()),
+        location: &product_data.manufacturing_location,
+        actor_id: caller,
+        now: current_time,
+    })?;
+
+    let product_id = next_product_id(current_time);
+
     let product = Product {
         id: product_id.clone(),
         name: product_data.name,
         category: product_data.category,
         description: product_data.description,
-        manufacturer: user.company.clone(),
+        manufacturer: organisation.as_ref().map_or_else(|| user.company.clone(), |organisation| organisation.name.clone()),
         manufacturer_id: caller,
         batch_number: product_data.batch_number,
         production_date: product_data.production_date,
@@ -136,11 +650,15 @@ fn register_product(product_data: ProductRegistration) -> Result<String, String>
         current_location: product_data.manufacturing_location.clone(),
         created_at: current_time,
         updated_at: current_time,
+        custodian: Some(caller),
+        pending_handoff: None,
+        organisation_id: organisation.map(|organisation| organisation.id),
+        certification_alerts: None,
     };
 
     // Create initial supply chain event
     let initial_event = SupplyChainEvent {
-        id: generate_event_id(),
+        id: next_event_id(),
         product_id: product_id.clone(),
         stage: SupplyChainStage::RawMaterialSourcing,
         location: product_data.manufacturing_location,
@@ -152,278 +670,2450 @@ fn register_product(product_data: ProductRegistration) -> Result<String, String>
         certifications: product_data.certifications,
         estimated_arrival: None,
         metadata: std::collections::HashMap::new(),
+        kind: None,
+        lot_id: None,
+        previous_hash: String::new(),
+        hash: String::new(),
+        superseded_by: None,
     };
 
-    PRODUCTS.with(|products| {
-        products.borrow_mut().insert(product_id.clone(), product);
-    });
+    store_product(product);
 
-    SUPPLY_CHAIN_EVENTS.with(|events| {
-        events.borrow_mut().insert(product_id.clone(), SupplyChainEventList(vec![initial_event]));
-    });
+    append_supply_chain_event(initial_event);
+    publish_certified_heads();
 
     Ok(product_id)
 }
 
-#[query]
-fn get_product(product_id: String) -> Result<ProductWithHistory, String> {
-    let product = PRODUCTS.with(|products| {
-        products.borrow().get(&product_id)
-            .ok_or_else(|| "Product not found".to_string())
+// Applies a field-level patch. Identity fields are frozen once the product has
+// been shipped; every edit is recorded in the product's change log.
+#[update]
+fn update_product(product_id: String, update: ProductUpdate) -> Result<Product, String> {
+    let caller = ic_cdk::caller();
+    let mut product = get_product_by_id(&product_id)?;
+
+    if !acts_for_manufacturer(&product, &caller) && !is_admin(&caller)? {
+        return Err("Unauthorized: Only the manufacturer or an admin can update this product".to_string());
+    }
+
+    if update.name.as_ref().is_some_and(|name| name.trim().is_empty()) {
+        return Err("Product name cannot be empty".to_string());
+    }
+
+    // References kept from before the registry existed are left alone
+    if let Some(certifications) = &update.certifications {
+        let added: Vec<String> = certifications.iter()
+            .filter(|certification| !product.certifications.contains(certification))
+            .cloned()
+            .collect();
+        let batch_number = match &update.batch_number {
+            Some(batch_number) => batch_number.as_ref(),
+            None => product.batch_number.as_ref(),
+        };
+        certifications::validate_references(&added, &CertificationContext {
+            product_id: Some(&product_id),
+            batch_number: batch_number.map(String::as_str),
+            manufacturer_id: product.manufacturer_id,
+            organisation_id: product.organisation_id.as_deref(),
+            location: &product.current_location,
+            actor_id: caller,
+            now: time(),
+        })?;
+    }
+
+    let mut changes = Vec::new();
+    apply_field_change(&mut changes, "name", &mut product.name, update.name);
+    apply_field_change(&mut changes, "category", &mut product.category, update.category);
+    apply_field_change(&mut changes, "description", &mut product.description, update.description);
+    apply_field_change(&mut changes, "batch_number", &mut product.batch_number, update.batch_number);
+    apply_field_change(&mut changes, "production_date", &mut product.production_date, update.production_date);
+    apply_field_change(&mut changes, "raw_materials", &mut product.raw_materials, update.raw_materials);
+    apply_field_change(&mut changes, "certifications", &mut product.certifications, update.certifications);
+    apply_field_change(&mut changes, "sustainability_score", &mut product.sustainability_score, update.sustainability_score);
+    apply_field_change(&mut changes, "estimated_value", &mut product.estimated_value, update.estimated_value);
+
+    if changes.is_empty() {
+        return Err("No changes to apply".to_string());
+    }
+
+    if has_shipped(&product_id) {
+        if let Some(change) = changes.iter().find(|change| SHIPMENT_LOCKED_FIELDS.contains(&change.field.as_str())) {
+            return Err(format!("Field '{}' cannot be changed after the product has shipped", change.field));
+        }
+    }
+
+    // Alerts about certifications the product no longer references are stale
+    if let Some(alerts) = product.certification_alerts.as_mut() {
+        alerts.retain(|alert| product.certifications.contains(&alert.certification_id));
+    }
+
+    let current_time = time();
+    product.updated_at = current_time;
+    store_product(product.clone());
+    record_product_change(ProductChange {
+        product_id,
+        changed_by: caller,
+        changed_at: current_time,
+        changes,
+        reason: update.reason,
+    });
+
+    Ok(product)
+}
+
+#[query]
+fn get_product_change_log(product_id: String, page: Option<PageRequest>) -> Result<Page<ProductChange>, String> {
+    let (_, level) = get_visible_product(&product_id, &ic_cdk::caller())?;
+
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let first_sequence = page.cursor.as_deref().map(decode_sequence_cursor).transpose()?.unwrap_or(0);
+
+    Ok(PRODUCT_CHANGES.with(|changes| {
+        let changes = changes.borrow();
+        let total_count_hint = changes.range(product_event_range(&product_id)).count() as u64;
+        let entries = changes
+            .range(Key(product_id.clone(), first_sequence)..=Key(product_id.clone(), u64::MAX))
+            .filter_map(|(key, change)| match level {
+                AccessLevel::Redacted => access::redact_change(change).map(|change| (key, change)),
+                _ => Some((key, change)),
+            });
+        collect_page(entries, page_size(page.limit), Some(total_count_hint), |Key(_, sequence)| sequence.to_string())
+    }))
+}
+
+#[query]
+fn get_product(product_id: String) -> Result<ProductWithHistory, String> {
+    load_product_with_history(&product_id, &ic_cdk::caller())
+}
+
+// Certified variant of get_product: the witness proves the returned head hash
+// under the canister's certified data, which the certificate signs.
+#[query]
+fn get_certified_product(product_id: String) -> Result<CertifiedProduct, String> {
+    let product = load_product_with_history(&product_id, &ic_cdk::caller())?;
+    let (certificate, witness) = certify_product_head(&product_id)?;
+
+    Ok(CertifiedProduct {
+        product,
+        certificate,
+        witness,
+    })
+}
+
+// Without a sort key results are ordered by product ID; sorted results break
+// ties by product ID so cursors stay stable across pages
+#[query]
+fn search_products(query: ProductSearchQuery) -> Result<Page<Product>, String> {
+    match &query.sort_by {
+        Some(sort_key) => search_products_sorted(&query, sort_key),
+        None => search_products_by_id(&query),
+    }
+}
+
+// Free-text search over product fields and event details, ranked by relevance.
+// Each query word matches indexed words it is a prefix of. Event metadata only
+// counts for products the caller has full access to.
+#[query]
+fn search_text(query: String, page: Option<PageRequest>) -> Result<Page<TextSearchHit>, String> {
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let after = match page.cursor.as_deref().map(decode_cursor).transpose()? {
+        Some(position) => {
+            let (score, product_id) = position.split_once(':')
+                .and_then(|(score, product_id)| Some((score.parse::<u32>().ok()?, product_id.to_string())))
+                .ok_or_else(|| "Invalid cursor".to_string())?;
+            Some((Reverse(score), product_id))
+        }
+        None => None,
+    };
+
+    // Ranking only sees what the caller may see, so hidden products are neither
+    // counted nor returned
+    let viewer = Viewer::resolve(ic_cdk::caller());
+    let ranked = text_index::search(&query, |product_id| {
+        get_product_by_id(product_id).map_or(AccessLevel::Hidden, |product| access::access_level(&product, &viewer))
+    });
+    let total_count_hint = ranked.len() as u64;
+    let entries = ranked
+        .into_iter()
+        .filter(|(product_id, score)| {
+            after.as_ref().is_none_or(|after| (Reverse(*score), product_id) > (after.0, &after.1))
+        })
+        .filter_map(|(product_id, score)| {
+            let product = visible_product(get_product_by_id(&product_id).ok()?, &viewer)?;
+            Some(((score, product_id), TextSearchHit { product, score }))
+        });
+
+    Ok(collect_page(entries, page_size(page.limit), Some(total_count_hint), |(score, product_id)| {
+        format!("{}:{}", score, product_id)
+    }))
+}
+
+// Supply Chain Event Functions
+#[update]
+fn add_supply_chain_event(event_data: SupplyChainEventInput) -> Result<String, SupplyChainError> {
+    let caller = ic_cdk::caller();
+    
+    // Verify user exists and has permission
+    let user = get_user_by_principal(&caller)?;
+    if !user.permissions.can_update_supply_chain {
+        return Err("Unauthorized: Cannot update supply chain".to_string().into());
+    }
+
+    // Verify product exists
+    let product = PRODUCTS.with(|products| {
+        products.borrow().get(&event_data.product_id)
+            .ok_or_else(|| "Product not found".to_string())
+    })?;
+
+    let event = prepare_stage_event(&product, &user, event_data, None, time())?;
+    let event_id = record_stage_event(product, event);
+    publish_certified_heads();
+
+    Ok(event_id)
+}
+
+// Corrections are appended as new events; the original stays in the history and
+// is reported as superseded by the amendment
+#[update]
+fn amend_supply_chain_event(amendment: SupplyChainEventAmendment) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+
+    let original = get_event_by_id(&amendment.event_id)?;
+
+    if original.actor_id != caller && !is_admin(&caller)? {
+        return Err("Unauthorized: Only the actor who recorded the event or an admin can amend it".to_string());
+    }
+
+    if !matches!(original.kind, None | Some(EventKind::Amendment { .. })) {
+        return Err("Only stage updates can be amended".to_string());
+    }
+
+    if let Some(amendment_id) = get_amendment_id(&original.id) {
+        return Err(format!("Event has already been amended by {}", amendment_id));
+    }
+
+    if amendment.reason.trim().is_empty() {
+        return Err("Amendment reason is required".to_string());
+    }
+
+    let current_time = time();
+    let product = get_product_by_id(&original.product_id)?;
+    let added: Vec<String> = amendment.certifications.iter()
+        .filter(|certification| !original.certifications.contains(certification))
+        .cloned()
+        .collect();
+    certifications::validate_references(&added, &CertificationContext {
+        product_id: Some(&product.id),
+        batch_number: product.batch_number.as_deref(),
+        manufacturer_id: product.manufacturer_id,
+        organisation_id: product.organisation_id.as_deref(),
+        location: &amendment.location,
+        actor_id: caller,
+        now: current_time,
+    })?;
+
+    let event_id = next_event_id();
+
+    let event = SupplyChainEvent {
+        id: event_id.clone(),
+        product_id: original.product_id.clone(),
+        stage: original.stage,
+        location: amendment.location.clone(),
+        timestamp: current_time,
+        actor: user.company,
+        actor_id: caller,
+        status: amendment.status,
+        details: amendment.details,
+        certifications: amendment.certifications,
+        estimated_arrival: amendment.estimated_arrival,
+        metadata: amendment.metadata,
+        kind: Some(EventKind::Amendment {
+            amends: original.id,
+            reason: amendment.reason,
+        }),
+        lot_id: original.lot_id,
+        previous_hash: String::new(),
+        hash: String::new(),
+        superseded_by: None,
+    };
+
+    record_amendment(product, event);
+    publish_certified_heads();
+
+    Ok(event_id)
+}
+
+#[query]
+fn verify_product_history(product_id: String) -> Result<HistoryVerification, String> {
+    get_visible_product(&product_id, &ic_cdk::caller())?;
+
+    let events = get_product_events(&product_id);
+    let first_invalid_event = verify_event_chain(&events);
+
+    Ok(HistoryVerification {
+        product_id,
+        valid: first_invalid_event.is_none(),
+        event_count: events.len() as u64,
+        head_hash: events.last().map(|event| event.hash.clone()),
+        first_invalid_event,
+    })
+}
+
+#[query]
+fn get_allowed_next_stages(product_id: String) -> Result<Vec<SupplyChainStage>, String> {
+    let (product, _) = get_visible_product(&product_id, &ic_cdk::caller())?;
+
+    Ok(allowed_next_stages(&product))
+}
+
+#[query]
+fn get_stage_transition_rules() -> Vec<StageTransitionRule> {
+    TRANSITION_RULES.with(|rules| rules.borrow().get().0.clone())
+}
+
+#[update]
+fn set_stage_transition_rules(rules: Vec<StageTransitionRule>) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller)? {
+        return Err("Unauthorized: Admin access required".to_string());
+    }
+
+    TRANSITION_RULES.with(|stored| {
+        stored.borrow_mut()
+            .set(StageTransitionRules(rules))
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store transition rules: {:?}", e))
+    })
+}
+
+#[query]
+fn get_supply_chain_events(product_id: String, page: Option<PageRequest>) -> Result<Page<SupplyChainEvent>, String> {
+    let (_, level) = get_visible_product(&product_id, &ic_cdk::caller())?;
+
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let first_sequence = page.cursor.as_deref().map(decode_sequence_cursor).transpose()?.unwrap_or(0);
+
+    Ok(SUPPLY_CHAIN_EVENTS.with(|events| {
+        let events = events.borrow();
+        let total_count_hint = events.range(product_event_range(&product_id)).count() as u64;
+        let entries = events.range(Key(product_id.clone(), first_sequence)..=Key(product_id.clone(), u64::MAX));
+        let mut page = collect_page(entries, page_size(page.limit), Some(total_count_hint), |Key(_, sequence)| sequence.to_string());
+        mark_superseded_events(&mut page.items);
+        if level == AccessLevel::Redacted {
+            page.items = page.items.into_iter().map(access::redact_event).collect();
+        }
+        page
+    }))
+}
+
+#[query]
+fn get_certified_supply_chain_events(product_id: String) -> Result<CertifiedSupplyChainEvents, String> {
+    let (_, level) = get_visible_product(&product_id, &ic_cdk::caller())?;
+
+    let mut events = get_product_events(&product_id);
+    mark_superseded_events(&mut events);
+    if level == AccessLevel::Redacted {
+        events = events.into_iter().map(access::redact_event).collect();
+    }
+    let (certificate, witness) = certify_product_head(&product_id)?;
+
+    Ok(CertifiedSupplyChainEvents {
+        head_hash: events.last().map(|event| event.hash.clone()),
+        product_id,
+        events,
+        certificate,
+        witness,
+    })
+}
+
+// Product Access Functions
+#[update]
+fn set_product_visibility(product_id: String, visibility: ProductVisibility) -> Result<(), String> {
+    let mut access = product_access_for_owner(&product_id)?;
+    access.visibility = visibility;
+    store_product_access(product_id, access);
+    Ok(())
+}
+
+#[update]
+fn grant_product_access(product_id: String, grant: AccessGrant) -> Result<(), String> {
+    let mut access = product_access_for_owner(&product_id)?;
+    if !access.grants.contains(&grant) {
+        access.grants.push(grant);
+    }
+    store_product_access(product_id, access);
+    Ok(())
+}
+
+#[update]
+fn revoke_product_access(product_id: String, grant: AccessGrant) -> Result<(), String> {
+    let mut access = product_access_for_owner(&product_id)?;
+    access.grants.retain(|existing| *existing != grant);
+    store_product_access(product_id, access);
+    Ok(())
+}
+
+#[query]
+fn get_product_access(product_id: String) -> Result<ProductAccess, String> {
+    product_access_for_owner(&product_id)
+}
+
+// Custody Transfer Functions
+// Offers custody to `to`. The custodian may re-initiate to replace a handoff
+// that has not been accepted yet.
+#[update]
+fn initiate_handoff(product_id: String, to: Principal) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+    let mut product = get_product_by_id(&product_id)?;
+
+    if current_custodian(&product) != caller {
+        return Err("Unauthorized: Only the current custodian can hand over this product".to_string());
+    }
+
+    if to == caller {
+        return Err("Product is already in your custody".to_string());
+    }
+
+    get_user_by_principal(&to).map_err(|_| "Recipient not found".to_string())?;
+
+    let current_time = time();
+    let event_id = append_custody_event(
+        &product,
+        &user,
+        current_time,
+        EventKind::HandoffInitiated { to },
+        format!("Custody handoff to {} initiated", to.to_text()),
+    );
+    publish_certified_heads();
+
+    product.pending_handoff = Some(CustodyHandoff {
+        from: caller,
+        to,
+        initiated_at: current_time,
+        event_id: event_id.clone(),
+    });
+    product.updated_at = current_time;
+    store_product(product);
+
+    Ok(event_id)
+}
+
+#[update]
+fn accept_handoff(product_id: String) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+    let mut product = get_product_by_id(&product_id)?;
+
+    let handoff = match &product.pending_handoff {
+        Some(handoff) if handoff.to == caller => handoff.clone(),
+        _ => return Err("No pending handoff to you for this product".to_string()),
+    };
+
+    let current_time = time();
+    let event_id = append_custody_event(
+        &product,
+        &user,
+        current_time,
+        EventKind::HandoffAccepted { from: handoff.from },
+        format!("Custody accepted from {}", handoff.from.to_text()),
+    );
+    publish_certified_heads();
+
+    product.custodian = Some(caller);
+    product.pending_handoff = None;
+    product.updated_at = current_time;
+    transfer_lot_custody(&product.id, caller, current_time);
+    store_product(product);
+
+    Ok(event_id)
+}
+
+// Lot Management Functions
+#[update]
+fn create_lot(lot_data: LotRequest) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+    if !user.permissions.can_update_supply_chain {
+        return Err("Unauthorized: Cannot update supply chain".to_string());
+    }
+
+    validate_lot_quantity(lot_data.quantity)?;
+    if lot_data.unit.trim().is_empty() {
+        return Err("Lot unit is required".to_string());
+    }
+
+    let mut product_ids = Vec::new();
+    for product_id in lot_data.product_ids {
+        if !product_ids.contains(&product_id) {
+            product_ids.push(product_id);
+        }
+    }
+    if product_ids.is_empty() {
+        return Err("A lot must contain at least one product".to_string());
+    }
+    for product_id in &product_ids {
+        let product = get_product_by_id(product_id)?;
+        if !acts_for_manufacturer(&product, &caller) && current_custodian(&product) != caller {
+            return Err(format!("Unauthorized: Product {} is not held by the caller", product_id));
+        }
+    }
+
+    let current_time = time();
+    let lot_id = next_lot_id();
+    store_lot(Lot {
+        id: lot_id.clone(),
+        product_ids,
+        quantity: lot_data.quantity,
+        unit: lot_data.unit.trim().to_string(),
+        batch_number: lot_data.batch_number,
+        location: lot_data.location,
+        holder: caller,
+        status: LotStatus::Active,
+        parent_ids: Vec::new(),
+        created_at: current_time,
+        updated_at: current_time,
+    });
+
+    Ok(lot_id)
+}
+
+// Splits a lot into portions whose quantities add up to the lot's quantity and
+// whose products, if listed, cover the lot's products exactly once. Returns the
+// new lot IDs in the order of `portions`.
+#[update]
+fn split_lot(lot_id: String, portions: Vec<LotPortion>) -> Result<Vec<String>, String> {
+    let caller = ic_cdk::caller();
+    let mut lot = get_lot_for_holder(&lot_id, &caller)?;
+
+    if portions.len() < 2 {
+        return Err("A lot must be split into at least two portions".to_string());
+    }
+
+    for portion in &portions {
+        validate_lot_quantity(portion.quantity)?;
+        if let Some(product_id) = portion.product_ids.iter().find(|product_id| !lot.product_ids.contains(product_id)) {
+            return Err(format!("Product {} is not in lot {}", product_id, lot_id));
+        }
+    }
+
+    // Either every portion names its products, splitting the lot's products
+    // between them, or none does and each portion keeps all of them
+    if portions.iter().any(|portion| !portion.product_ids.is_empty()) {
+        if portions.iter().any(|portion| portion.product_ids.is_empty()) {
+            return Err("Either every portion or no portion must list its products".to_string());
+        }
+        let mut assigned: Vec<&String> = Vec::new();
+        for product_id in portions.iter().flat_map(|portion| portion.product_ids.iter()) {
+            if assigned.contains(&product_id) {
+                return Err(format!("Product {} is in more than one portion", product_id));
+            }
+            assigned.push(product_id);
+        }
+        if let Some(product_id) = lot.product_ids.iter().find(|product_id| !assigned.contains(product_id)) {
+            return Err(format!("Product {} of lot {} is not in any portion", product_id, lot_id));
+        }
+    }
+    let total: f64 = portions.iter().map(|portion| portion.quantity).sum();
+    if !same_quantity(total, lot.quantity) {
+        return Err(format!("Portions add up to {} {} but the lot holds {} {}", total, lot.unit, lot.quantity, lot.unit));
+    }
+
+    let current_time = time();
+    let mut child_ids = Vec::new();
+    for portion in portions {
+        let child_id = next_lot_id();
+        let product_ids = if portion.product_ids.is_empty() { lot.product_ids.clone() } else { portion.product_ids };
+        store_lot(Lot {
+            id: child_id.clone(),
+            product_ids,
+            quantity: portion.quantity,
+            unit: lot.unit.clone(),
+            batch_number: lot.batch_number.clone(),
+            location: lot.location.clone(),
+            holder: lot.holder,
+            status: LotStatus::Active,
+            parent_ids: vec![lot_id.clone()],
+            created_at: current_time,
+            updated_at: current_time,
+        });
+        record_lot_edge(LotEdge {
+            parent_id: lot_id.clone(),
+            child_id: child_id.clone(),
+            operation: LotOperation::Split,
+            quantity: portion.quantity,
+            recorded_by: caller,
+            recorded_at: current_time,
+        });
+        child_ids.push(child_id);
+    }
+
+    lot.status = LotStatus::Split;
+    lot.updated_at = current_time;
+    store_lot(lot);
+
+    Ok(child_ids)
+}
+
+// Merges lots of the same unit at the same location into a new lot holding all
+// of their products
+#[update]
+fn merge_lots(lot_ids: Vec<String>) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+
+    let mut lots: Vec<Lot> = Vec::new();
+    for lot_id in &lot_ids {
+        if lots.iter().any(|lot| lot.id == *lot_id) {
+            return Err(format!("Lot {} is listed more than once", lot_id));
+        }
+        lots.push(get_lot_for_holder(lot_id, &caller)?);
+    }
+    if lots.len() < 2 {
+        return Err("At least two lots are needed for a merge".to_string());
+    }
+
+    let first = &lots[0];
+    if let Some(lot) = lots.iter().find(|lot| lot.unit != first.unit) {
+        return Err(format!("Lot {} is measured in {} rather than {}", lot.id, lot.unit, first.unit));
+    }
+    if let Some(lot) = lots.iter().find(|lot| !lot.location.trim().eq_ignore_ascii_case(first.location.trim())) {
+        return Err(format!("Lot {} is at {} rather than {}", lot.id, lot.location, first.location));
+    }
+    if let Some(lot) = lots.iter().find(|lot| lot.holder != first.holder) {
+        return Err(format!("Lot {} has a different holder", lot.id));
+    }
+
+    let mut product_ids: Vec<String> = Vec::new();
+    for product_id in lots.iter().flat_map(|lot| lot.product_ids.iter()) {
+        if !product_ids.contains(product_id) {
+            product_ids.push(product_id.clone());
+        }
+    }
+    let batch_number = first.batch_number.clone()
+        .filter(|batch_number| lots.iter().all(|lot| lot.batch_number.as_ref() == Some(batch_number)));
+
+    let current_time = time();
+    let merged_id = next_lot_id();
+    store_lot(Lot {
+        id: merged_id.clone(),
+        product_ids,
+        quantity: lots.iter().map(|lot| lot.quantity).sum(),
+        unit: first.unit.clone(),
+        batch_number,
+        location: first.location.clone(),
+        holder: first.holder,
+        status: LotStatus::Active,
+        parent_ids: lot_ids,
+        created_at: current_time,
+        updated_at: current_time,
+    });
+
+    for mut lot in lots {
+        record_lot_edge(LotEdge {
+            parent_id: lot.id.clone(),
+            child_id: merged_id.clone(),
+            operation: LotOperation::Merge,
+            quantity: lot.quantity,
+            recorded_by: caller,
+            recorded_at: current_time,
+        });
+        lot.status = LotStatus::Merged;
+        lot.updated_at = current_time;
+        store_lot(lot);
+    }
+
+    Ok(merged_id)
+}
+
+// Records the holder's stage update on every product in the lot. Nothing is recorded
+// unless the update is valid for all of them. Returns the event IDs in the
+// order of the lot's products.
+#[update]
+fn add_lot_event(lot_id: String, event_data: LotEventInput) -> Result<Vec<String>, SupplyChainError> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+    if !user.permissions.can_update_supply_chain {
+        return Err("Unauthorized: Cannot update supply chain".to_string().into());
+    }
+
+    let mut lot = get_lot_for_holder(&lot_id, &caller)?;
+
+    let current_time = time();
+    let mut prepared = Vec::new();
+    for product_id in &lot.product_ids {
+        let product = get_product_by_id(product_id)?;
+        let product_event = SupplyChainEventInput {
+            product_id: product_id.clone(),
+            stage: event_data.stage.clone(),
+            location: event_data.location.clone(),
+            status: event_data.status.clone(),
+            details: event_data.details.clone(),
+            certifications: event_data.certifications.clone(),
+            estimated_arrival: event_data.estimated_arrival,
+            metadata: event_data.metadata.clone(),
+        };
+        let event = prepare_stage_event(&product, &user, product_event, Some(lot_id.clone()), current_time)
+            .map_err(|e| match e {
+                SupplyChainError::Rejected(message) => format!("Product {}: {}", product_id, message).into(),
+                e => e,
+            })?;
+        prepared.push((product, event));
+    }
+
+    let event_ids = prepared
+        .into_iter()
+        .map(|(product, event)| record_stage_event(product, event))
+        .collect();
+    publish_certified_heads();
+
+    lot.location = event_data.location;
+    lot.updated_at = current_time;
+    store_lot(lot);
+
+    Ok(event_ids)
+}
+
+#[query]
+fn get_lot(lot_id: String) -> Result<Lot, String> {
+    get_visible_lot(&lot_id, &Viewer::resolve(ic_cdk::caller()))
+}
+
+// Every split and merge the lot descends from and every lot created from it
+#[query]
+fn get_lot_genealogy(lot_id: String) -> Result<LotGenealogy, String> {
+    let viewer = Viewer::resolve(ic_cdk::caller());
+    let lot = get_visible_lot(&lot_id, &viewer)?;
+
+    let mut ancestors = Vec::new();
+    let mut visited = vec![lot_id.clone()];
+    let mut queue = std::collections::VecDeque::from([lot.clone()]);
+    while let Some(child) = queue.pop_front() {
+        for parent_id in &child.parent_ids {
+            if let Some(edge) = LOT_EDGES.with(|edges| edges.borrow().get(&Key(parent_id.clone(), child.id.clone()))) {
+                ancestors.push(edge);
+            }
+            if !visited.contains(parent_id) {
+                visited.push(parent_id.clone());
+                queue.push_back(get_lot_by_id(parent_id)?);
+            }
+        }
+    }
+
+    let mut descendants = Vec::new();
+    let mut visited = vec![lot_id.clone()];
+    let mut queue = std::collections::VecDeque::from([lot_id]);
+    while let Some(parent_id) = queue.pop_front() {
+        for edge in lot_children(&parent_id) {
+            if !visited.contains(&edge.child_id) {
+                visited.push(edge.child_id.clone());
+                queue.push_back(edge.child_id.clone());
+            }
+            descendants.push(edge);
+        }
+    }
+
+    // Edges to lots the caller cannot see are left out
+    let edge_visible = |edge: &LotEdge| {
+        get_visible_lot(&edge.parent_id, &viewer).is_ok() && get_visible_lot(&edge.child_id, &viewer).is_ok()
+    };
+    ancestors.retain(edge_visible);
+    descendants.retain(edge_visible);
+
+    Ok(LotGenealogy {
+        lot,
+        ancestors,
+        descendants,
+    })
+}
+
+#[query]
+fn list_lots(page: Option<PageRequest>) -> Result<Page<Lot>, String> {
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let start = match page.cursor.as_deref().map(decode_cursor).transpose()? {
+        Some(lot_id) => Bound::Excluded(lot_id),
+        None => Bound::Unbounded,
+    };
+
+    let viewer = Viewer::resolve(ic_cdk::caller());
+    Ok(LOTS.with(|lots| {
+        let lots = lots.borrow();
+        let entries = lots
+            .range((start, Bound::Unbounded))
+            .filter_map(|(lot_id, lot)| Some((lot_id, visible_lot(lot, &viewer)?)));
+        collect_page(entries, page_size(page.limit), None, |lot_id| lot_id.clone())
+    }))
+}
+
+// Recall Management Functions
+#[update]
+fn initiate_recall(recall_data: RecallRequest) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+    // Everyone else may only recall products made by them or their organisation
+    let can_recall_any = user.role == UserRole::QualityAssurance || is_admin(&caller)?;
+
+    if recall_data.reason.trim().is_empty() {
+        return Err("Recall reason is required".to_string());
+    }
+
+    let mut affected_products = Vec::new();
+    for product_id in &recall_data.product_ids {
+        let product = get_product_by_id(product_id)?;
+        if !can_recall_any && !acts_for_manufacturer(&product, &caller) {
+            return Err(format!("Unauthorized: Cannot recall product {}", product_id));
+        }
+        affected_products.push(product);
+    }
+
+    // Manufacturers only match their own products when recalling by batch number
+    if let Some(batch_number) = &recall_data.batch_number {
+        for product_id in index::batch_product_ids(batch_number) {
+            if affected_products.iter().any(|affected| affected.id == product_id) {
+                continue;
+            }
+            let product = get_product_by_id(&product_id)?;
+            if can_recall_any || acts_for_manufacturer(&product, &caller) {
+                affected_products.push(product);
+            }
+        }
+    }
+
+    if affected_products.is_empty() {
+        return Err("No products match the recall".to_string());
+    }
+
+    let recall_id = format!("RCL-{:06}", next_id_sequence("recall"));
+    let current_time = time();
+
+    for mut product in affected_products.iter().cloned() {
+        let stage = get_last_product_event(&product.id)
+            .map_or(SupplyChainStage::RawMaterialSourcing, |event| event.stage);
+
+        append_supply_chain_event(SupplyChainEvent {
+            id: next_event_id(),
+            product_id: product.id.clone(),
+            stage,
+            location: product.current_location.clone(),
+            timestamp: current_time,
+            actor: user.company.clone(),
+            actor_id: caller,
+            status: EventStatus::Completed,
+            details: format!("Recall {} ({:?}): {}", recall_id, recall_data.severity, recall_data.reason),
+            certifications: Vec::new(),
+            estimated_arrival: None,
+            metadata: std::collections::HashMap::new(),
+            kind: Some(EventKind::Recall { recall_id: recall_id.clone() }),
+            lot_id: None,
+            previous_hash: String::new(),
+            hash: String::new(),
+            superseded_by: None,
+        });
+
+        product.current_status = ProductStatus::Recalled;
+        product.updated_at = current_time;
+        store_product(product);
+    }
+    publish_certified_heads();
+
+    let recall = Recall {
+        id: recall_id.clone(),
+        reason: recall_data.reason,
+        severity: recall_data.severity,
+        product_ids: affected_products.into_iter().map(|product| product.id).collect(),
+        batch_number: recall_data.batch_number,
+        status: RecallStatus::Open,
+        initiated_by: caller,
+        acknowledgements: Vec::new(),
+        created_at: current_time,
+        updated_at: current_time,
+        closed_at: None,
+    };
+
+    RECALLS.with(|recalls| {
+        recalls.borrow_mut().insert(recall_id.clone(), recall);
+    });
+
+    Ok(recall_id)
+}
+
+#[update]
+fn update_recall_status(recall_id: String, status: RecallStatus) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+    let mut recall = get_recall_by_id(&recall_id)?;
+
+    if recall.initiated_by != caller && user.role != UserRole::QualityAssurance && !is_admin(&caller)? {
+        return Err("Unauthorized: Cannot manage this recall".to_string());
+    }
+
+    // Recalls only move forward: Open -> InProgress -> Closed
+    let allowed = matches!(
+        (&recall.status, &status),
+        (RecallStatus::Open, RecallStatus::InProgress)
+            | (RecallStatus::Open, RecallStatus::Closed)
+            | (RecallStatus::InProgress, RecallStatus::Closed)
+    );
+    if !allowed {
+        return Err(format!("Invalid recall status change: {:?} to {:?}", recall.status, status));
+    }
+
+    let current_time = time();
+    if status == RecallStatus::Closed {
+        recall.closed_at = Some(current_time);
+    }
+    recall.status = status;
+    recall.updated_at = current_time;
+
+    RECALLS.with(|recalls| {
+        recalls.borrow_mut().insert(recall_id, recall);
+    });
+
+    Ok(())
+}
+
+#[update]
+fn acknowledge_recall(recall_id: String) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+    let mut recall = get_recall_by_id(&recall_id)?;
+
+    if recall.status == RecallStatus::Closed {
+        return Err("Recall is already closed".to_string());
+    }
+
+    if recall.acknowledgements.iter().any(|ack| ack.actor_id == caller) {
+        return Err("Recall already acknowledged".to_string());
+    }
+
+    // Only actors that handled an affected product receive the notice
+    let handled_product = recall.product_ids.iter().any(|product_id| {
+        get_product_events(product_id)
+            .iter()
+            .any(|event| event.actor_id == caller && event.kind.is_none())
+    });
+    if !handled_product {
+        return Err("Unauthorized: Caller did not handle any recalled product".to_string());
+    }
+
+    let current_time = time();
+    recall.acknowledgements.push(RecallAcknowledgement {
+        actor: user.company,
+        actor_id: caller,
+        acknowledged_at: current_time,
+    });
+    recall.updated_at = current_time;
+
+    RECALLS.with(|recalls| {
+        recalls.borrow_mut().insert(recall_id, recall);
+    });
+
+    Ok(())
+}
+
+#[query]
+fn get_recall(recall_id: String) -> Result<Recall, String> {
+    get_visible_recall(&recall_id, &Viewer::resolve(ic_cdk::caller()))
+}
+
+#[query]
+fn get_recalls(status: Option<RecallStatus>, page: Option<PageRequest>) -> Result<Page<Recall>, String> {
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let start = match page.cursor.as_deref().map(decode_cursor).transpose()? {
+        Some(recall_id) => Bound::Excluded(recall_id),
+        None => Bound::Unbounded,
+    };
+
+    let viewer = Viewer::resolve(ic_cdk::caller());
+    Ok(RECALLS.with(|recalls| {
+        let recalls = recalls.borrow();
+        let entries = recalls
+            .range((start, Bound::Unbounded))
+            .filter(|(_, recall)| status.as_ref().is_none_or(|status| recall.status == *status))
+            .filter_map(|(recall_id, recall)| Some((recall_id, visible_recall(recall, &viewer)?)));
+        collect_page(entries, page_size(page.limit), Some(recalls.len()), |recall_id| recall_id.clone())
+    }))
+}
+
+// Partner Management Functions
+#[update]
+fn register_partner(partner_data: PartnerRegistration) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    
+    // Verify user exists and has permission
+    let user = get_user_by_principal(&caller)?;
+    if !user.permissions.can_manage_partners {
+        return Err("Unauthorized: Cannot manage partners".to_string());
+    }
+
+    let current_time = time();
+    let partner_id = format!("PTR-{:06}", next_id_sequence("partner"));
+
+    let partner = Partner {
+        id: partner_id.clone(),
+        manager_id: caller,
+        company_name: partner_data.company_name,
+        partner_type: partner_data.partner_type,
+        contact_email: partner_data.contact_email,
+        contact_person: partner_data.contact_person,
+        certifications: partner_data.certifications,
+        status: PartnerStatus::Pending,
+        status_history: vec![PartnerStatusChange {
+            status: PartnerStatus::Pending,
+            changed_by: caller,
+            changed_at: current_time,
+            reason: None,
+        }],
+        created_at: current_time,
+        updated_at: current_time,
+        reputation_score: 0,
+        certification_alerts: None,
+    };
+
+    store_partner(partner);
+
+    Ok(partner_id)
+}
+
+#[update]
+fn update_partner(partner_id: String, update: PartnerUpdate) -> Result<Partner, String> {
+    let caller = ic_cdk::caller();
+    let mut partner = get_partner_by_id(&partner_id)?;
+
+    if partner.manager_id != caller && !is_admin(&caller)? {
+        return Err("Unauthorized: Only the partner's manager or an admin can update it".to_string());
+    }
+
+    if partner.status == PartnerStatus::Removed {
+        return Err("Partner has been removed".to_string());
+    }
+
+    if let Some(contact_email) = update.contact_email {
+        partner.contact_email = contact_email;
+    }
+    if let Some(contact_person) = update.contact_person {
+        partner.contact_person = contact_person;
+    }
+    partner.updated_at = time();
+
+    store_partner(partner.clone());
+
+    Ok(partner)
+}
+
+#[update]
+fn verify_partner(partner_id: String, reason: Option<String>) -> Result<Partner, String> {
+    change_partner_status(&partner_id, PartnerStatus::Verified, reason)
+}
+
+#[update]
+fn suspend_partner(partner_id: String, reason: Option<String>) -> Result<Partner, String> {
+    change_partner_status(&partner_id, PartnerStatus::Suspended, reason)
+}
+
+#[update]
+fn remove_partner(partner_id: String, reason: Option<String>) -> Result<Partner, String> {
+    change_partner_status(&partner_id, PartnerStatus::Removed, reason)
+}
+
+// Counterparties rate a partner they worked with on `product_id`, from 1 to 5.
+// Rating the same partner again replaces the earlier rating.
+#[update]
+fn rate_partner(partner_id: String, product_id: String, rating: u8) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+    get_user_by_principal(&caller)?;
+    let partner = get_partner_by_id(&partner_id)?;
+
+    if !(reputation::MIN_RATING..=reputation::MAX_RATING).contains(&rating) {
+        return Err(format!("Rating must be between {} and {}", reputation::MIN_RATING, reputation::MAX_RATING));
+    }
+
+    if partner.status == PartnerStatus::Removed {
+        return Err("Partner has been removed".to_string());
+    }
+
+    if reputation::partners_for_actor(&caller).iter().any(|own| own.id == partner_id) {
+        return Err("Partners cannot rate themselves".to_string());
+    }
+
+    let events = get_product_events(&product_id);
+    let caller_handled = events.iter().any(|event| event.kind.is_none() && event.actor_id == caller);
+    if !caller_handled || !reputation::handled_product(&product_id, &partner_id) {
+        return Err("Unauthorized: Only counterparties who handled the product with this partner can rate it".to_string());
+    }
+
+    let previous = PARTNER_RATINGS.with(|ratings| ratings.borrow_mut().insert(Key(partner_id, caller), rating));
+    reputation::record_rating(partner, previous, rating);
+
+    Ok(())
+}
+
+#[query]
+fn get_partner_reputation(partner_id: String) -> Result<ReputationBreakdown, String> {
+    get_partner_by_id(&partner_id)?;
+    Ok(reputation::breakdown(&partner_id))
+}
+
+#[query]
+fn get_partner(partner_id: String) -> Result<Partner, String> {
+    get_partner_by_id(&partner_id)
+}
+
+// Lists partners that have not been removed
+#[query]
+fn get_partners(page: Option<PageRequest>) -> Result<Page<Partner>, String> {
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let start = match page.cursor.as_deref().map(decode_cursor).transpose()? {
+        Some(partner_id) => Bound::Excluded(partner_id),
+        None => Bound::Unbounded,
+    };
+
+    Ok(PARTNERS.with(|partners| {
+        let partners = partners.borrow();
+        let total_count_hint = partners.iter().filter(|(_, partner)| partner.status != PartnerStatus::Removed).count() as u64;
+        let entries = partners
+            .range((start, Bound::Unbounded))
+            .filter(|(_, partner)| partner.status != PartnerStatus::Removed);
+        collect_page(entries, page_size(page.limit), Some(total_count_hint), |partner_id| partner_id.clone())
+    }))
+}
+
+// Certification Registry Functions
+// Certifications are issued by the manager of a verified certification body
+// partner and referenced by ID from products and events
+#[update]
+fn issue_certification(request: CertificationRequest) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let issuer = get_partner_by_id(&request.issuer_id)?;
+
+    if issuer.manager_id != caller {
+        return Err("Unauthorized: Only the issuing partner's manager can issue certifications".to_string());
+    }
+
+    if issuer.partner_type != PartnerType::CertificationBody {
+        return Err("Only certification bodies can issue certifications".to_string());
+    }
+
+    if issuer.status != PartnerStatus::Verified {
+        return Err("Certification body has not been verified".to_string());
+    }
+
+    if request.standard.trim().is_empty() {
+        return Err("Certification standard is required".to_string());
+    }
+
+    if request.valid_until <= request.valid_from {
+        return Err("Certification must be valid until after it becomes valid".to_string());
+    }
+
+    match &request.subject {
+        CertificationSubject::Product(product_id) => {
+            get_product_by_id(product_id)?;
+        }
+        CertificationSubject::Partner(partner_id) => {
+            get_partner_by_id(partner_id)?;
+        }
+        CertificationSubject::Batch { manufacturer, .. } => match manufacturer {
+            CertificationHolder::Principal(principal) => {
+                get_user_by_principal(principal)?;
+            }
+            CertificationHolder::Organisation(organisation_id) => {
+                get_organisation_by_id(organisation_id)?;
+            }
+        },
+        CertificationSubject::Facility(facility_id) => {
+            get_facility_by_id(facility_id)?;
+        }
+    }
+
+    let certification_id = format!("CERT-{:06}", next_id_sequence("certification"));
+    let certification = Certification {
+        id: certification_id.clone(),
+        standard: request.standard.trim().to_string(),
+        scope: request.scope,
+        issuer_id: request.issuer_id,
+        subject: request.subject,
+        valid_from: request.valid_from,
+        valid_until: request.valid_until,
+        issued_by: caller,
+        issued_at: time(),
+        revoked_at: None,
+        revocation_reason: None,
+        notified_state: None,
+    };
+
+    CERTIFICATIONS.with(|certifications| {
+        certifications.borrow_mut().insert(certification_id.clone(), certification);
+    });
+
+    Ok(certification_id)
+}
+
+// Revocation is final. Existing references stay in the history but the
+// certification can no longer be attached to new records.
+#[update]
+fn revoke_certification(certification_id: String, reason: String) -> Result<Certification, String> {
+    let caller = ic_cdk::caller();
+    let mut certification = get_certification_by_id(&certification_id)?;
+    let issuer = get_partner_by_id(&certification.issuer_id)?;
+
+    if issuer.manager_id != caller && !is_admin(&caller)? {
+        return Err("Unauthorized: Only the issuer or an admin can revoke this certification".to_string());
+    }
+
+    if certification.revoked_at.is_some() {
+        return Err("Certification has already been revoked".to_string());
+    }
+
+    if reason.trim().is_empty() {
+        return Err("Revocation reason is required".to_string());
+    }
+
+    certification.revoked_at = Some(time());
+    certification.revocation_reason = Some(reason);
+
+    CERTIFICATIONS.with(|certifications| {
+        certifications.borrow_mut().insert(certification_id, certification.clone());
+    });
+
+    Ok(certification)
+}
+
+// Facilities belong to the caller's organisation, or to the caller without one
+#[update]
+fn register_facility(facility_data: FacilityRequest) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let user = get_user_by_principal(&caller)?;
+
+    if facility_data.name.trim().is_empty() || facility_data.location.trim().is_empty() {
+        return Err("Facility name and location are required".to_string());
+    }
+
+    let facility_id = format!("FAC-{:06}", next_id_sequence("facility"));
+    let facility = Facility {
+        id: facility_id.clone(),
+        name: facility_data.name.trim().to_string(),
+        location: facility_data.location.trim().to_string(),
+        owner: user.organisation_id.map_or(CertificationHolder::Principal(caller), CertificationHolder::Organisation),
+        registered_by: caller,
+        created_at: time(),
+    };
+
+    FACILITIES.with(|facilities| {
+        facilities.borrow_mut().insert(facility_id.clone(), facility);
+    });
+
+    Ok(facility_id)
+}
+
+#[query]
+fn get_facility(facility_id: String) -> Result<Facility, String> {
+    get_facility_by_id(&facility_id)
+}
+
+#[query]
+fn get_certification(certification_id: String) -> Result<Certification, String> {
+    get_certification_by_id(&certification_id)
+}
+
+// Lists certifications, optionally only those issued for `subject`
+#[query]
+fn list_certifications(subject: Option<CertificationSubject>, page: Option<PageRequest>) -> Result<Page<Certification>, String> {
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let start = match page.cursor.as_deref().map(decode_cursor).transpose()? {
+        Some(certification_id) => Bound::Excluded(certification_id),
+        None => Bound::Unbounded,
+    };
+
+    Ok(CERTIFICATIONS.with(|certifications| {
+        let certifications = certifications.borrow();
+        let entries = certifications
+            .range((start, Bound::Unbounded))
+            .filter(|(_, certification)| subject.as_ref().is_none_or(|subject| certification.subject == *subject));
+        collect_page(entries, page_size(page.limit), Some(certifications.len()), |certification_id| certification_id.clone())
+    }))
+}
+
+// Notification Functions
+// The caller's notifications, oldest first
+#[query]
+fn get_notifications(unread_only: bool, page: Option<PageRequest>) -> Result<Page<Notification>, String> {
+    let caller = ic_cdk::caller();
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let first_id = page.cursor.as_deref().map(decode_sequence_cursor).transpose()?.unwrap_or(0);
+
+    Ok(NOTIFICATIONS.with(|notifications| {
+        let notifications = notifications.borrow();
+        let total_count_hint = notifications.range((caller, 0)..=(caller, u64::MAX)).count() as u64;
+        let entries = notifications
+            .range((caller, first_id)..=(caller, u64::MAX))
+            .filter(|(_, notification)| !unread_only || !notification.read);
+        collect_page(entries, page_size(page.limit), Some(total_count_hint), |(_, id)| id.to_string())
+    }))
+}
+
+#[update]
+fn mark_notification_read(notification_id: u64) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    NOTIFICATIONS.with(|notifications| {
+        let mut notifications = notifications.borrow_mut();
+        let mut notification = notifications.get(&(caller, notification_id))
+            .ok_or_else(|| "Notification not found".to_string())?;
+        notification.read = true;
+        notifications.insert((caller, notification_id), notification);
+        Ok(())
+    })
+}
+
+// Ethical Scoring Functions
+// Stores a new policy version and makes it the active one
+#[update]
+fn set_scoring_policy(input: ScoringPolicyInput) -> Result<u32, String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller)? {
+        return Err("Unauthorized: Admin access required".to_string());
+    }
+
+    let policy = ScoringPolicy {
+        version: active_scoring_policy().version + 1,
+        base_score: input.base_score,
+        certification_weights: input.certification_weights,
+        default_certification_points: input.default_certification_points,
+        certified_event_points: input.certified_event_points,
+        failed_event_penalty: input.failed_event_penalty,
+        recall_penalty: input.recall_penalty,
+        raw_material_risks: input.raw_material_risks,
+        lapsed_certification_points: input.lapsed_certification_points,
+        created_by: caller,
+        created_at: time(),
+    };
+    scoring::validate_policy(&policy)?;
+
+    let version = policy.version;
+    SCORING_POLICIES.with(|policies| policies.borrow_mut().insert(version, policy));
+
+    Ok(version)
+}
+
+// The active policy when `version` is None
+#[query]
+fn get_scoring_policy(version: Option<u32>) -> Result<ScoringPolicy, String> {
+    match version {
+        Some(version) => get_scoring_policy_by_version(version),
+        None => Ok(active_scoring_policy()),
+    }
+}
+
+// Explains a product's current ethical score under the active policy, or under
+// an earlier `policy_version` to compare policies. Inputs are read as they are
+// now, so this is not the score a product had when that version was active.
+#[query]
+fn get_ethical_score_breakdown(product_id: String, policy_version: Option<u32>) -> Result<EthicalScoreBreakdown, String> {
+    get_visible_product(&product_id, &ic_cdk::caller())?;
+
+    let policy = match policy_version {
+        Some(version) => get_scoring_policy_by_version(version)?,
+        None => active_scoring_policy(),
+    };
+
+    ethical_score_breakdown(&product_id, &policy)
+}
+
+// Analytics Functions
+#[query]
+fn get_analytics() -> AnalyticsData {
+    let total_products = PRODUCTS.with(|products| products.borrow().len());
+    
+    let (active_shipments, completed_deliveries) = PRODUCTS.with(|products| {
+        products.borrow()
+            .iter()
+            .fold((0u64, 0u64), |(active, completed), (_, product)| {
+                match product.current_status {
+                    ProductStatus::InTransit => (active + 1, completed),
+                    ProductStatus::Delivered => (active, completed + 1),
+                    _ => (active, completed),
+                }
+            })
+    });
+
+    let average_ethical_score = PRODUCTS.with(|products| {
+        let products_map = products.borrow();
+        if products_map.is_empty() {
+            return 0.0;
+        }
+        
+        let total_score: f64 = products_map
+            .iter()
+            .map(|(product_id, _)| calculate_ethical_score(&product_id).unwrap_or(0.0))
+            .sum();
+        
+        total_score / products_map.len() as f64
+    });
+
+    AnalyticsData {
+        total_products,
+        active_shipments,
+        completed_deliveries,
+        average_ethical_score,
+        total_partners: PARTNERS.with(|partners| {
+            partners.borrow()
+                .iter()
+                .filter(|(_, partner)| partner.status != PartnerStatus::Removed)
+                .count() as u64
+        }),
+        total_users: USERS.with(|users| users.borrow().len()),
+    }
+}
+
+// Label Functions
+#[query]
+fn get_product_qr_code(product_id: String) -> Result<String, String> {
+    get_visible_product(&product_id, &ic_cdk::caller())?;
+    labels::product_qr_svg(&product_id)
+}
+
+#[query]
+fn get_product_label(product_id: String) -> Result<String, String> {
+    let (product, _) = get_visible_product(&product_id, &ic_cdk::caller())?;
+    labels::product_label_svg(&product)
+}
+
+#[update]
+fn set_public_base_url(base_url: String) -> Result<(), String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller)? {
+        return Err("Unauthorized: Admin access required".to_string());
+    }
+
+    if !base_url.is_empty() && !base_url.starts_with("https://") && !base_url.starts_with("http://") {
+        return Err("Base URL must start with https:// or http://".to_string());
+    }
+
+    PUBLIC_BASE_URL.with(|url| {
+        url.borrow_mut()
+            .set(base_url)
+            .map(|_| ())
+            .map_err(|e| format!("Failed to store base URL: {:?}", e))
+    })
+}
+
+// HTTP Gateway Functions
+// Serves /product/{id} (HTML), /product/{id}/qr.svg, /product/{id}/label.svg and
+// /api/product/{id} (JSON) to browsers without an agent
+#[query]
+fn http_request(request: HttpRequest) -> HttpResponse {
+    http::handle_http_request(&request)
+}
+
+// Utility Functions
+#[query]
+fn get_canister_status() -> CanisterStatus {
+    CanisterStatus {
+        version: "1.0.0".to_string(),
+        total_products: PRODUCTS.with(|products| products.borrow().len()),
+        total_users: USERS.with(|users| users.borrow().len()),
+        total_events: SUPPLY_CHAIN_EVENTS.with(|events| events.borrow().len()),
+        uptime: time(),
+    }
+}
+
+// Helper Functions
+fn get_user_by_principal(principal: &Principal) -> Result<User, String> {
+    USERS.with(|users| {
+        users.borrow().get(principal)
+            .ok_or_else(|| "User not found".to_string())
+    })
+}
+
+// The product and its history as `viewer` is allowed to see them
+fn load_product_with_history(product_id: &str, viewer: &Principal) -> Result<ProductWithHistory, String> {
+    let (product, level) = get_visible_product(product_id, viewer)?;
+    let mut events = get_product_events(product_id);
+    mark_superseded_events(&mut events);
+    if level == AccessLevel::Redacted {
+        events = events.into_iter().map(access::redact_event).collect();
+    }
+
+    Ok(ProductWithHistory {
+        product,
+        head_hash: events.last().map(|event| event.hash.clone()),
+        supply_chain_events: events,
+        ethical_score: calculate_ethical_score(product_id)?,
+    })
+}
+
+fn list_users_page(page: Option<PageRequest>, include: impl Fn(&User) -> bool) -> Result<Page<User>, String> {
+    let page = page.unwrap_or(PageRequest { cursor: None, limit: None });
+    let start = principal_cursor_bound(page.cursor.as_deref())?;
+
+    Ok(USERS.with(|users| {
+        let users = users.borrow();
+        let entries = users
+            .range((start, Bound::Unbounded))
+            .filter(|(_, user)| include(user));
+        collect_page(entries, page_size(page.limit), Some(users.len()), |id| id.to_text())
+    }))
+}
+
+// Start bound for maps keyed by principal, resuming after the cursor's principal
+fn principal_cursor_bound(cursor: Option<&str>) -> Result<Bound<Principal>, String> {
+    match cursor.map(decode_cursor).transpose()? {
+        Some(position) => Principal::from_text(position)
+            .map(Bound::Excluded)
+            .map_err(|_| "Invalid cursor".to_string()),
+        None => Ok(Bound::Unbounded),
+    }
+}
+
+// Every product write goes through here so the secondary indexes stay in sync
+fn store_product(product: Product) {
+    let previous = PRODUCTS.with(|products| products.borrow_mut().insert(product.id.clone(), product.clone()));
+    index::reindex_product(previous.as_ref(), &product);
+    text_index::reindex_product(previous.as_ref(), &product);
+    certifications::reindex_product_references(previous.as_ref(), &product);
+}
+
+// Fields that identify the physical goods and must not change once they have shipped
+const SHIPMENT_LOCKED_FIELDS: &[&str] = &["name", "category", "batch_number", "production_date", "raw_materials"];
+
+fn apply_field_change<T: PartialEq + std::fmt::Debug>(
+    changes: &mut Vec<FieldChange>,
+    field: &str,
+    current: &mut T,
+    new_value: Option<T>,
+) {
+    if let Some(new_value) = new_value {
+        if *current != new_value {
+            changes.push(FieldChange {
+                field: field.to_string(),
+                old_value: format!("{:?}", current),
+                new_value: format!("{:?}", new_value),
+            });
+            *current = new_value;
+        }
+    }
+}
+
+fn visible_product(product: Product, viewer: &Viewer) -> Option<Product> {
+    let level = access::access_level(&product, viewer);
+    apply_access_level(product, level)
+}
+
+// Hidden products are reported as missing so their existence does not leak
+fn get_visible_product(product_id: &str, viewer: &Principal) -> Result<(Product, AccessLevel), String> {
+    let product = get_product_by_id(product_id)?;
+    let level = access::access_level(&product, &Viewer::resolve(*viewer));
+    let product = apply_access_level(product, level)
+        .ok_or_else(|| "Product not found".to_string())?;
+    Ok((product, level))
+}
+
+fn apply_access_level(product: Product, level: AccessLevel) -> Option<Product> {
+    match level {
+        AccessLevel::Full => Some(product),
+        AccessLevel::Redacted => Some(access::redact_product(product)),
+        AccessLevel::Hidden => None,
+    }
+}
+
+// Access settings may only be read and changed by the manufacturer and admins
+fn product_access_for_owner(product_id: &str) -> Result<ProductAccess, String> {
+    let caller = ic_cdk::caller();
+    let product = get_product_by_id(product_id)?;
+
+    if !acts_for_manufacturer(&product, &caller) && !is_admin(&caller)? {
+        return Err("Unauthorized: Only the manufacturer or an admin can manage product access".to_string());
+    }
+
+    Ok(access::product_access(product_id))
+}
+
+fn store_product_access(product_id: String, access: ProductAccess) {
+    PRODUCT_ACCESS.with(|entries| {
+        entries.borrow_mut().insert(product_id, access);
+    });
+}
+
+fn get_partner_by_id(partner_id: &str) -> Result<Partner, String> {
+    PARTNERS.with(|partners| {
+        partners.borrow().get(&partner_id.to_string())
+            .ok_or_else(|| "Partner not found".to_string())
+    })
+}
+
+fn store_partner(partner: Partner) {
+    index_partner_manager(&partner);
+    let previous = PARTNERS.with(|partners| partners.borrow_mut().insert(partner.id.clone(), partner.clone()));
+    certifications::reindex_partner_references(previous.as_ref(), &partner);
+}
+
+// Managers never change, so entries are only ever added
+fn index_partner_manager(partner: &Partner) {
+    PARTNERS_BY_MANAGER.with(|index| {
+        index.borrow_mut().insert(Key(partner.manager_id, partner.id.clone()), ());
+    });
+}
+
+fn partners_managed_by(manager_id: &Principal) -> Vec<Partner> {
+    let partner_ids: Vec<String> = PARTNERS_BY_MANAGER.with(|index| {
+        index.borrow()
+            .range(Key(*manager_id, String::new())..)
+            .take_while(|(Key(indexed_manager_id, _), _)| indexed_manager_id == manager_id)
+            .map(|(Key(_, partner_id), _)| partner_id)
+            .collect()
+    });
+    partner_ids.iter().filter_map(|partner_id| get_partner_by_id(partner_id).ok()).collect()
+}
+
+// Admin-only status changes. Removal is final; every change is appended to the
+// partner's status history.
+fn change_partner_status(partner_id: &str, status: PartnerStatus, reason: Option<String>) -> Result<Partner, String> {
+    let caller = ic_cdk::caller();
+
+    if !is_admin(&caller)? {
+        return Err("Unauthorized: Admin access required".to_string());
+    }
+
+    let mut partner = get_partner_by_id(partner_id)?;
+
+    if partner.status == PartnerStatus::Removed {
+        return Err("Partner has been removed".to_string());
+    }
+
+    if partner.status == status {
+        return Err(format!("Partner is already {:?}", status));
+    }
+
+    let current_time = time();
+    partner.status = status.clone();
+    partner.status_history.push(PartnerStatusChange {
+        status,
+        changed_by: caller,
+        changed_at: current_time,
+        reason,
+    });
+    partner.updated_at = current_time;
+
+    store_partner(partner.clone());
+
+    Ok(partner)
+}
+
+fn get_certification_by_id(certification_id: &str) -> Result<Certification, String> {
+    certifications::get_certification(certification_id)
+        .ok_or_else(|| "Certification not found".to_string())
+}
+
+fn get_facility_by_id(facility_id: &str) -> Result<Facility, String> {
+    FACILITIES.with(|facilities| {
+        facilities.borrow().get(&facility_id.to_string())
+            .ok_or_else(|| "Facility not found".to_string())
+    })
+}
+
+fn get_organisation_by_id(organisation_id: &str) -> Result<Organisation, String> {
+    ORGANISATIONS.with(|organisations| {
+        organisations.borrow().get(&organisation_id.to_string())
+            .ok_or_else(|| "Organisation not found".to_string())
+    })
+}
+
+fn store_organisation(organisation: Organisation) {
+    ORGANISATION_NAMES.with(|names| {
+        names.borrow_mut().insert(normalize_company_name(&organisation.name), organisation.id.clone());
+    });
+    ORGANISATIONS.with(|organisations| {
+        organisations.borrow_mut().insert(organisation.id.clone(), organisation);
+    });
+}
+
+fn organisation_role(organisation: &Organisation, principal: &Principal) -> Option<OrganisationRole> {
+    organisation.members.iter()
+        .find(|member| member.user_id == *principal)
+        .map(|member| member.role)
+}
+
+// Owners and admins manage members; owners are only appointed or removed by
+// other owners. Canister admins may do either.
+fn authorize_membership_change(organisation: &Organisation, caller: &Principal, role: OrganisationRole) -> Result<(), String> {
+    if is_admin(caller)? {
+        return Ok(());
+    }
+
+    match organisation_role(organisation, caller) {
+        Some(OrganisationRole::Owner) => Ok(()),
+        Some(OrganisationRole::Admin) if role != OrganisationRole::Owner => Ok(()),
+        _ => Err("Unauthorized: Organisation owner or admin access required".to_string()),
+    }
+}
+
+// Members of the owning organisation act as the product's manufacturer
+fn acts_for_manufacturer(product: &Product, principal: &Principal) -> bool {
+    match &product.organisation_id {
+        Some(organisation_id) => get_user_by_principal(principal)
+            .is_ok_and(|user| user.organisation_id.as_ref() == Some(organisation_id)),
+        None => product.manufacturer_id == *principal,
+    }
+}
+
+// A partner is represented by its manager and, when the manager belongs to an
+// organisation, by the other members of that organisation
+fn acts_for_partner(partner: &Partner, principal: &Principal) -> bool {
+    if partner.manager_id == *principal {
+        return true;
+    }
+
+    let Some(organisation_id) = get_user_by_principal(&partner.manager_id).ok().and_then(|manager| manager.organisation_id) else {
+        return false;
+    };
+    get_user_by_principal(principal).is_ok_and(|user| user.organisation_id == Some(organisation_id))
+}
+
+fn acts_for_holder(holder: &CertificationHolder, principal: &Principal) -> bool {
+    match holder {
+        CertificationHolder::Principal(holder) => holder == principal,
+        CertificationHolder::Organisation(organisation_id) => get_user_by_principal(principal)
+            .is_ok_and(|user| user.organisation_id.as_ref() == Some(organisation_id)),
+    }
+}
+
+fn current_custodian(product: &Product) -> Principal {
+    product.custodian.unwrap_or(product.manufacturer_id)
+}
+
+fn is_logistics_stage(stage: &SupplyChainStage) -> bool {
+    matches!(stage, SupplyChainStage::Shipping | SupplyChainStage::Distribution)
+}
+
+// Handoff events repeat the product's current stage and status so they do not
+// affect stage transitions
+fn append_custody_event(product: &Product, actor: &User, timestamp: u64, kind: EventKind, details: String) -> String {
+    let (stage, status) = get_last_product_event(&product.id)
+        .map_or((SupplyChainStage::RawMaterialSourcing, EventStatus::Completed), |event| (event.stage, event.status));
+    let event_id = next_event_id();
+
+    append_supply_chain_event(SupplyChainEvent {
+        id: event_id.clone(),
+        product_id: product.id.clone(),
+        stage,
+        location: product.current_location.clone(),
+        timestamp,
+        actor: actor.company.clone(),
+        actor_id: actor.id,
+        status,
+        details,
+        certifications: Vec::new(),
+        estimated_arrival: None,
+        metadata: std::collections::HashMap::new(),
+        kind: Some(kind),
+        lot_id: None,
+        previous_hash: String::new(),
+        hash: String::new(),
+        superseded_by: None,
+    });
+
+    event_id
+}
+
+// Checks that `user` may record the stage update on `product` and builds the
+// event without writing anything; the ID is assigned by record_stage_event
+fn prepare_stage_event(
+    product: &Product,
+    user: &User,
+    event_data: SupplyChainEventInput,
+    lot_id: Option<String>,
+    timestamp: u64,
+) -> Result<SupplyChainEvent, SupplyChainError> {
+    if is_logistics_stage(&event_data.stage) && current_custodian(product) != user.id {
+        return Err("Unauthorized: Only the current custodian can record logistics events".to_string().into());
+    }
+
+    validate_stage_transition(product, &event_data.stage)?;
+
+    certifications::validate_references(&event_data.certifications, &CertificationContext {
+        product_id: Some(&product.id),
+        batch_number: product.batch_number.as_deref(),
+        manufacturer_id: product.manufacturer_id,
+        organisation_id: product.organisation_id.as_deref(),
+        location: &event_data.location,
+        actor_id: user.id,
+        now: timestamp,
     })?;
 
-    let events = SUPPLY_CHAIN_EVENTS.with(|events| {
-        events.borrow().get(&product_id)
-            .map(|event_list| event_list.0)
-            .unwrap_or_default()
+    Ok(SupplyChainEvent {
+        id: String::new(),
+        product_id: product.id.clone(),
+        stage: event_data.stage,
+        location: event_data.location,
+        timestamp,
+        actor: user.company.clone(),
+        actor_id: user.id,
+        status: event_data.status,
+        details: event_data.details,
+        certifications: event_data.certifications,
+        estimated_arrival: event_data.estimated_arrival,
+        metadata: event_data.metadata,
+        kind: None,
+        lot_id,
+        previous_hash: String::new(),
+        hash: String::new(),
+        superseded_by: None,
+    })
+}
+
+// Appends a prepared stage update and moves the product to its status and location
+fn record_stage_event(mut product: Product, mut event: SupplyChainEvent) -> String {
+    let event_id = next_event_id();
+    event.id = event_id.clone();
+
+    product.current_status = stage_to_product_status(&event.stage);
+    product.current_location = event.location.clone();
+    product.updated_at = event.timestamp;
+
+    store_product(product);
+
+    append_supply_chain_event(event);
+
+    event_id
+}
+
+// Appends an amendment. Correcting the product's current stage update also
+// corrects where the product is now.
+fn record_amendment(mut product: Product, event: SupplyChainEvent) {
+    let event_id = event.id.clone();
+    let location = event.location.clone();
+    let timestamp = event.timestamp;
+    let stage = event.stage.clone();
+
+    append_supply_chain_event(event);
+
+    if last_stage_update_id(&product.id).as_deref() == Some(event_id.as_str()) {
+        if product.current_status != ProductStatus::Recalled {
+            product.current_status = stage_to_product_status(&stage);
+        }
+        product.current_location = location;
+        product.updated_at = timestamp;
+        store_product(product);
+    }
+}
+
+// The latest stage update of the product, as last amended
+fn last_stage_update_id(product_id: &str) -> Option<String> {
+    LAST_STAGE_UPDATES.with(|updates| updates.borrow().get(&product_id.to_string()))
+}
+
+fn get_lot_by_id(lot_id: &str) -> Result<Lot, String> {
+    LOTS.with(|lots| {
+        lots.borrow().get(&lot_id.to_string())
+            .ok_or_else(|| "Lot not found".to_string())
+    })
+}
+
+// Holders and admins see the whole lot. Others only see the products they have
+// full access to, and lots without any such product are hidden.
+fn visible_lot(mut lot: Lot, viewer: &Viewer) -> Option<Lot> {
+    if lot.holder == viewer.principal || viewer.is_admin {
+        return Some(lot);
+    }
+
+    lot.product_ids.retain(|product_id| {
+        get_product_by_id(product_id)
+            .is_ok_and(|product| access::access_level(&product, viewer) == AccessLevel::Full)
+    });
+    (!lot.product_ids.is_empty()).then_some(lot)
+}
+
+fn get_visible_lot(lot_id: &str, viewer: &Viewer) -> Result<Lot, String> {
+    visible_lot(get_lot_by_id(lot_id)?, viewer)
+        .ok_or_else(|| "Lot not found".to_string())
+}
+
+// Loads an open lot that `principal` may split or merge
+fn get_lot_for_holder(lot_id: &str, principal: &Principal) -> Result<Lot, String> {
+    let lot = get_lot_by_id(lot_id)?;
+
+    if lot.holder != *principal && !is_admin(principal)? {
+        return Err(format!("Unauthorized: Lot {} is held by another principal", lot_id));
+    }
+
+    if lot.status != LotStatus::Active {
+        return Err(format!("Lot {} is closed ({:?})", lot_id, lot.status));
+    }
+
+    Ok(lot)
+}
+
+// Hands open lots containing the product to `new_holder` once it has custody of
+// every product in them. Called before the product itself is stored, so its
+// custody is taken as already transferred.
+fn transfer_lot_custody(product_id: &str, new_holder: Principal, timestamp: u64) {
+    let lots: Vec<Lot> = active_lot_ids(product_id)
+        .into_iter()
+        .filter_map(|lot_id| get_lot_by_id(&lot_id).ok())
+        .filter(|lot| lot.holder != new_holder)
+        .collect();
+
+    for mut lot in lots {
+        let holds_all = lot.product_ids.iter().all(|id| {
+            id == product_id || get_product_by_id(id).is_ok_and(|product| current_custodian(&product) == new_holder)
+        });
+        if holds_all {
+            lot.holder = new_holder;
+            lot.updated_at = timestamp;
+            store_lot(lot);
+        }
+    }
+}
+
+fn store_lot(lot: Lot) {
+    let previous = LOTS.with(|lots| lots.borrow_mut().insert(lot.id.clone(), lot.clone()));
+    index_lot_products(previous.as_ref(), &lot);
+}
+
+// Keeps PRODUCT_LOTS in sync with the new version of a lot; lots that were
+// split or merged drop out of the index
+fn index_lot_products(previous: Option<&Lot>, lot: &Lot) {
+    PRODUCT_LOTS.with(|index| {
+        let mut index = index.borrow_mut();
+        for product_id in previous.iter().flat_map(|previous| previous.product_ids.iter()) {
+            index.remove(&Key(product_id.clone(), lot.id.clone()));
+        }
+        if lot.status == LotStatus::Active {
+            for product_id in &lot.product_ids {
+                index.insert(Key(product_id.clone(), lot.id.clone()), ());
+            }
+        }
+    });
+}
+
+fn active_lot_ids(product_id: &str) -> Vec<String> {
+    PRODUCT_LOTS.with(|index| {
+        index.borrow()
+            .range(Key(product_id.to_string(), String::new())..)
+            .take_while(|(Key(indexed_product_id, _), _)| indexed_product_id == product_id)
+            .map(|(Key(_, lot_id), _)| lot_id)
+            .collect()
+    })
+}
+
+fn record_lot_edge(edge: LotEdge) {
+    LOT_EDGES.with(|edges| {
+        edges.borrow_mut().insert(Key(edge.parent_id.clone(), edge.child_id.clone()), edge);
+    });
+}
+
+fn lot_children(lot_id: &str) -> Vec<LotEdge> {
+    LOT_EDGES.with(|edges| {
+        edges.borrow()
+            .range(Key(lot_id.to_string(), String::new())..)
+            .take_while(|(Key(parent_id, _), _)| parent_id == lot_id)
+            .map(|(_, edge)| edge)
+            .collect()
+    })
+}
+
+fn validate_lot_quantity(quantity: f64) -> Result<(), String> {
+    if !quantity.is_finite() || quantity <= 0.0 {
+        return Err("Lot quantity must be greater than zero".to_string());
+    }
+    Ok(())
+}
+
+// Portion quantities are floats, so their sum is compared with a relative tolerance
+fn same_quantity(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * a.abs().max(b.abs())
+}
+
+// Shipped once any event has reached a stage after Packaging, whether or not the
+// product passed through Shipping itself
+fn has_shipped(product_id: &str) -> bool {
+    get_product_events(product_id).iter().any(|event| {
+        matches!(event.stage, SupplyChainStage::Shipping | SupplyChainStage::Distribution | SupplyChainStage::Retail)
+    })
+}
+
+fn record_product_change(change: ProductChange) {
+    PRODUCT_CHANGES.with(|changes| {
+        let mut changes = changes.borrow_mut();
+        let sequence = changes.range(product_event_range(&change.product_id))
+            .next_back()
+            .map_or(0, |(Key(_, last_sequence), _)| last_sequence + 1);
+        changes.insert(Key(change.product_id.clone(), sequence), change);
+    });
+}
+
+fn search_products_by_id(query: &ProductSearchQuery) -> Result<Page<Product>, String> {
+    let limit = page_size(query.limit);
+    let after = query.cursor.as_deref().map(decode_cursor).transpose()?;
+    let matches = |product: &Product| product_matches_query(product, query);
+    let viewer = Viewer::resolve(ic_cdk::caller());
+
+    let page = match index::most_selective_scan(query) {
+        Some(scan) => {
+            let mut candidates = scan.product_ids();
+            candidates.sort();
+            let total_count_hint = candidates.len() as u64;
+            let entries = candidates
+                .into_iter()
+                .filter(|product_id| after.as_ref().is_none_or(|after| product_id > after))
+                .filter_map(|product_id| get_product_by_id(&product_id).ok())
+                .filter(|product| matches(product))
+                .filter_map(|product| visible_product(product, &viewer))
+                .map(|product| (product.id.clone(), product));
+            collect_page(entries, limit, Some(total_count_hint), |product_id| product_id.clone())
+        }
+        None => PRODUCTS.with(|products| {
+            let products = products.borrow();
+            let start = after.map_or(Bound::Unbounded, Bound::Excluded);
+            let entries = products
+                .range((start, Bound::Unbounded))
+                .filter(|(_, product)| matches(product))
+                .filter_map(|(product_id, product)| Some((product_id, visible_product(product, &viewer)?)));
+            collect_page(entries, limit, Some(products.len()), |product_id| product_id.clone())
+        }),
+    };
+
+    Ok(page)
+}
+
+// Sorting needs every match up front, so the cursor holds the last item's
+// position as "<sort value>:<product id>" rather than a map key
+fn search_products_sorted(query: &ProductSearchQuery, sort_key: &ProductSortKey) -> Result<Page<Product>, String> {
+    let limit = page_size(query.limit);
+    let descending = query.sort_order == Some(SortOrder::Descending);
+    let after = match query.cursor.as_deref().map(decode_cursor).transpose()? {
+        Some(position) => {
+            let (value, product_id) = position.split_once(':')
+                .ok_or_else(|| "Invalid cursor".to_string())?;
+            Some((value.to_string(), product_id.to_string()))
+        }
+        None => None,
+    };
+
+    let viewer = Viewer::resolve(ic_cdk::caller());
+    let candidates: Vec<Product> = match index::most_selective_scan(query) {
+        Some(scan) => scan.product_ids()
+            .into_iter()
+            .filter_map(|product_id| get_product_by_id(&product_id).ok())
+            .filter(|product| product_matches_query(product, query))
+            .collect(),
+        None => PRODUCTS.with(|products| {
+            products.borrow()
+                .iter()
+                .map(|(_, product)| product)
+                .filter(|product| product_matches_query(product, query))
+                .collect()
+        }),
+    };
+
+    // Sort on the visible product so redacted fields do not leak through the order
+    let mut entries: Vec<((String, String), Product)> = candidates
+        .into_iter()
+        .filter_map(|product| visible_product(product, &viewer))
+        .map(|product| ((product_sort_value(&product, sort_key), product.id.clone()), product))
+        .collect();
+    entries.sort_by(|(a, _), (b, _)| if descending { b.cmp(a) } else { a.cmp(b) });
+
+    let total_count_hint = entries.len() as u64;
+    let entries = entries.into_iter().filter(|(position, _)| {
+        after.as_ref().is_none_or(|after| if descending { position < after } else { position > after })
     });
 
-    Ok(ProductWithHistory {
-        product,
-        supply_chain_events: events,
-        ethical_score: calculate_ethical_score(&product_id)?,
-    })
+    Ok(collect_page(entries, limit, Some(total_count_hint), |(value, product_id)| format!("{}:{}", value, product_id)))
 }
 
-#[query]
-fn search_products(query: ProductSearchQuery) -> Vec<Product> {
-    PRODUCTS.with(|products| {
-        products.borrow()
-            .iter()
-            .filter_map(|(_, product)| {
-                let matches_name = query.name.as_ref()
-                    .map_or(true, |name| product.name.to_lowercase().contains(&name.to_lowercase()));
-                
-                let matches_category = query.category.as_ref()
-                    .map_or(true, |category| product.category == *category);
-                
-                let matches_manufacturer = query.manufacturer.as_ref()
-                    .map_or(true, |manufacturer| product.manufacturer.to_lowercase().contains(&manufacturer.to_lowercase()));
-                
-                let matches_status = query.status.as_ref()
-                    .map_or(true, |status| product.current_status == *status);
-
-                if matches_name && matches_category && matches_manufacturer && matches_status {
-                    Some(product)
-                } else {
-                    None
-                }
-            })
-            .take(query.limit.unwrap_or(50) as usize)
-            .collect()
-    })
+// Zero-padded so that string order matches numeric order; products without an
+// estimated value sort before all others
+fn product_sort_value(product: &Product, sort_key: &ProductSortKey) -> String {
+    let value = match sort_key {
+        ProductSortKey::CreatedAt => product.created_at,
+        ProductSortKey::UpdatedAt => product.updated_at,
+        ProductSortKey::ProductionDate => product.production_date,
+        ProductSortKey::EthicalScore => sortable_f64(calculate_ethical_score(&product.id).unwrap_or(0.0)),
+        ProductSortKey::EstimatedValue => match product.estimated_value {
+            Some(value) => sortable_f64(value),
+            None => return String::new(),
+        },
+    };
+    format!("{:020}", value)
 }
 
-// Supply Chain Event Functions
-#[update]
-fn add_supply_chain_event(event_data: SupplyChainEventInput) -> Result<String, String> {
-    let caller = ic_cdk::caller();
-    
-    // Verify user exists and has permission
-    let user = get_user_by_principal(&caller)?;
-    if !user.permissions.can_update_supply_chain {
-        return Err("Unauthorized: Cannot update supply chain".to_string());
-    }
+fn product_matches_query(product: &Product, query: &ProductSearchQuery) -> bool {
+    let matches_name = query.name.as_ref()
+        .is_none_or(|name| product.name.to_lowercase().contains(&name.to_lowercase()));
 
-    // Verify product exists
-    let mut product = PRODUCTS.with(|products| {
-        products.borrow().get(&event_data.product_id)
-            .ok_or_else(|| "Product not found".to_string())
-    })?;
+    let matches_category = query.category.as_ref()
+        .is_none_or(|category| product.category == *category);
 
-    let event_id = generate_event_id();
-    let current_time = time();
+    let matches_manufacturer = query.manufacturer.as_ref()
+        .is_none_or(|manufacturer| product.manufacturer.to_lowercase().contains(&manufacturer.to_lowercase()));
 
-    let event = SupplyChainEvent {
-        id: event_id.clone(),
-        product_id: event_data.product_id.clone(),
-        stage: event_data.stage.clone(),
-        location: event_data.location.clone(),
-        timestamp: current_time,
-        actor: user.company.clone(),
-        actor_id: caller,
-        status: event_data.status,
-        details: event_data.details,
-        certifications: event_data.certifications,
-        estimated_arrival: event_data.estimated_arrival,
-        metadata: event_data.metadata,
-    };
+    let matches_manufacturer_id = query.manufacturer_id
+        .is_none_or(|manufacturer_id| product.manufacturer_id == manufacturer_id);
 
-    // Update product status and location
-    product.current_status = stage_to_product_status(&event_data.stage);
-    product.current_location = event_data.location;
-    product.updated_at = current_time;
+    let matches_organisation = query.organisation_id.as_ref()
+        .is_none_or(|organisation_id| product.organisation_id.as_ref() == Some(organisation_id));
 
-    PRODUCTS.with(|products| {
-        products.borrow_mut().insert(event_data.product_id.clone(), product);
+    let matches_status = query.status.as_ref()
+        .is_none_or(|status| product.current_status == *status);
+
+    let matches_batch = query.batch_number.as_ref()
+        .is_none_or(|batch_number| product.batch_number.as_ref() == Some(batch_number));
+
+    let matches_production_date = query.production_date_from.is_none_or(|from| product.production_date >= from)
+        && query.production_date_to.is_none_or(|to| product.production_date <= to);
+
+    let matches_updated = query.updated_from.is_none_or(|from| product.updated_at >= from)
+        && query.updated_to.is_none_or(|to| product.updated_at <= to);
+
+    let matches_certifications = query.certifications.as_ref().is_none_or(|required| {
+        required.iter().all(|certification| {
+            // Registered certifications match by ID or by standard
+            product.certifications.iter().any(|held| {
+                held.eq_ignore_ascii_case(certification)
+                    || certifications::certification_label(held).eq_ignore_ascii_case(certification)
+            })
+        })
     });
 
-    SUPPLY_CHAIN_EVENTS.with(|events| {
-        let mut events_map = events.borrow_mut();
-        let mut product_events = events_map.get(&event_data.product_id)
-            .map(|event_list| event_list.0)
-            .unwrap_or_default();
-        product_events.push(event);
-        events_map.insert(event_data.product_id, SupplyChainEventList(product_events));
+    let matches_raw_materials = query.raw_materials.as_ref().is_none_or(|required| {
+        required.iter().all(|material| {
+            let material = material.to_lowercase();
+            product.raw_materials.iter().any(|used| used.to_lowercase().contains(&material))
+        })
     });
 
-    Ok(event_id)
+    let matches_location = query.location.as_ref()
+        .is_none_or(|location| product.current_location.to_lowercase().contains(&location.to_lowercase()));
+
+    matches_name && matches_category && matches_manufacturer && matches_manufacturer_id && matches_organisation
+        && matches_status && matches_batch && matches_production_date && matches_updated
+        && matches_certifications && matches_raw_materials && matches_location
 }
 
-#[query]
-fn get_supply_chain_events(product_id: String) -> Result<Vec<SupplyChainEvent>, String> {
-    // Verify product exists
+fn get_product_by_id(product_id: &str) -> Result<Product, String> {
     PRODUCTS.with(|products| {
-        products.borrow().get(&product_id)
+        products.borrow().get(&product_id.to_string())
             .ok_or_else(|| "Product not found".to_string())
-    })?;
+    })
+}
+
+fn get_recall_by_id(recall_id: &str) -> Result<Recall, String> {
+    RECALLS.with(|recalls| {
+        recalls.borrow().get(&recall_id.to_string())
+            .ok_or_else(|| "Recall not found".to_string())
+    })
+}
+
+// Recalls list only the products the viewer may see, and are hidden like a
+// missing recall when none remain
+fn visible_recall(mut recall: Recall, viewer: &Viewer) -> Option<Recall> {
+    if recall.initiated_by == viewer.principal || viewer.is_admin {
+        return Some(recall);
+    }
 
-    let events = SUPPLY_CHAIN_EVENTS.with(|events| {
-        events.borrow().get(&product_id)
-            .map(|event_list| event_list.0)
-            .unwrap_or_default()
+    recall.product_ids.retain(|product_id| {
+        get_product_by_id(product_id)
+            .is_ok_and(|product| access::access_level(&product, viewer) != AccessLevel::Hidden)
     });
+    (!recall.product_ids.is_empty()).then_some(recall)
+}
 
-    Ok(events)
+fn get_visible_recall(recall_id: &str, viewer: &Viewer) -> Result<Recall, String> {
+    visible_recall(get_recall_by_id(recall_id)?, viewer)
+        .ok_or_else(|| "Recall not found".to_string())
 }
 
-// Partner Management Functions
-#[update]
-fn register_partner(partner_data: PartnerRegistration) -> Result<(), String> {
-    let caller = ic_cdk::caller();
-    
-    // Verify user exists and has permission
-    let user = get_user_by_principal(&caller)?;
-    if !user.permissions.can_manage_partners {
-        return Err("Unauthorized: Cannot manage partners".to_string());
+fn is_admin(principal: &Principal) -> Result<bool, String> {
+    if is_bootstrap_admin(principal) {
+        return Ok(true);
     }
 
-    let partner = Partner {
-        id: caller,
-        company_name: partner_data.company_name,
-        partner_type: partner_data.partner_type,
-        contact_email: partner_data.contact_email,
-        contact_person: partner_data.contact_person,
-        certifications: partner_data.certifications,
-        verified: false,
-        created_at: time(),
-        reputation_score: 0,
-    };
+    let user = get_user_by_principal(principal)?;
+    Ok(user.role == UserRole::Admin)
+}
 
-    PARTNERS.with(|partners| {
-        partners.borrow_mut().insert(caller, partner);
+// Admins named in the init/upgrade arguments; when none were named the
+// canister controllers act as admins instead.
+fn is_bootstrap_admin(principal: &Principal) -> bool {
+    BOOTSTRAP_ADMINS.with(|admins| {
+        let admins = admins.borrow();
+        if admins.is_empty() {
+            ic_cdk::api::is_controller(principal)
+        } else {
+            admins.contains_key(principal)
+        }
+    })
+}
+
+fn add_bootstrap_admins(principals: Vec<Principal>) {
+    BOOTSTRAP_ADMINS.with(|admins| {
+        let mut admins = admins.borrow_mut();
+        for principal in principals {
+            if principal != Principal::anonymous() {
+                admins.insert(principal, ());
+            }
+        }
     });
+}
 
-    Ok(())
+fn set_user_role(user: &mut User, role: UserRole) {
+    user.role = role;
+    user.permissions = get_default_permissions(&role);
+    user.pending_role = None;
 }
 
-#[query]
-fn get_partners() -> Vec<Partner> {
-    PARTNERS.with(|partners| {
-        partners.borrow()
-            .iter()
-            .map(|(_, partner)| partner)
+fn get_product_events(product_id: &str) -> Vec<SupplyChainEvent> {
+    SUPPLY_CHAIN_EVENTS.with(|events| {
+        events.borrow()
+            .range(product_event_range(product_id))
+            .map(|(_, event)| event)
             .collect()
     })
 }
 
-// Analytics Functions
-#[query]
-fn get_analytics() -> AnalyticsData {
-    let total_products = PRODUCTS.with(|products| products.borrow().len());
-    
-    let (active_shipments, completed_deliveries) = PRODUCTS.with(|products| {
-        products.borrow()
-            .iter()
-            .fold((0u64, 0u64), |(active, completed), (_, product)| {
-                match product.current_status {
-                    ProductStatus::InTransit => (active + 1, completed),
-                    ProductStatus::Delivered => (active, completed + 1),
-                    _ => (active, completed),
+// Stores the event after the product's last one, links it into the product's
+// hash chain and returns its sequence number
+fn append_supply_chain_event(mut event: SupplyChainEvent) -> u64 {
+    let sequence = SUPPLY_CHAIN_EVENTS.with(|events| {
+        let mut events_map = events.borrow_mut();
+        let last = events_map.range(product_event_range(&event.product_id)).next_back();
+        let sequence = last.as_ref().map_or(0, |(Key(_, last_sequence), _)| last_sequence + 1);
+        event.previous_hash = last.map_or_else(|| GENESIS_HASH.to_string(), |(_, last_event)| last_event.hash);
+        event.hash = compute_event_hash(&event);
+        EVENT_IDS.with(|event_ids| {
+            event_ids.borrow_mut().insert(event.id.clone(), Key(event.product_id.clone(), sequence));
+        });
+        update_product_head(event.product_id.clone(), event.hash.clone());
+        text_index::index_event(&event);
+        EVENT_ACTORS.with(|actors| {
+            actors.borrow_mut().insert(Key(event.product_id.clone(), event.actor_id), ());
+        });
+        if let Some(EventKind::Amendment { amends, .. }) = &event.kind {
+            EVENT_AMENDMENTS.with(|amendments| {
+                amendments.borrow_mut().insert(amends.clone(), event.id.clone());
+            });
+        }
+        events_map.insert(Key(event.product_id.clone(), sequence), event.clone());
+        sequence
+    });
+    reputation::record_event(&event);
+    sequence
+}
+
+// Records the new head in the certified tree. Update calls that append events
+// publish the resulting root once, with publish_certified_heads, before returning.
+fn update_product_head(product_id: String, head_hash: String) {
+    CERTIFIED_HEADS.with(|heads| heads.borrow_mut().insert(product_id, head_hash));
+}
+
+fn publish_certified_heads() {
+    let root_hash = CERTIFIED_HEADS.with(|heads| heads.borrow().root_hash());
+    ic_cdk::api::set_certified_data(&root_hash);
+}
+
+fn rebuild_certified_heads() {
+    CERTIFIED_HEADS.with(|heads| {
+        let mut heads = heads.borrow_mut();
+        *heads = CertifiedHeads::default();
+        PRODUCTS.with(|products| {
+            for (product_id, _) in products.borrow().iter() {
+                if let Some(last_event) = get_last_product_event(&product_id) {
+                    heads.insert(product_id, last_event.hash);
                 }
-            })
+            }
+        });
     });
+    publish_certified_heads();
+}
 
-    let average_ethical_score = PRODUCTS.with(|products| {
-        let products_map = products.borrow();
-        if products_map.is_empty() {
-            return 0.0;
+// Returns the data certificate and CBOR witness for the product's head hash
+fn certify_product_head(product_id: &str) -> Result<(Vec<u8>, Vec<u8>), String> {
+    let certificate = ic_cdk::api::data_certificate()
+        .ok_or_else(|| "Certificate unavailable: call this method as a query".to_string())?;
+    let witness = CERTIFIED_HEADS.with(|heads| heads.borrow().witness(product_id).to_cbor());
+    Ok((certificate, witness))
+}
+
+// Recomputes every hash in the product's history and checks each link
+fn verify_event_chain(events: &[SupplyChainEvent]) -> Option<String> {
+    let mut previous_hash = GENESIS_HASH.to_string();
+    for event in events {
+        if event.previous_hash != previous_hash || event.hash != compute_event_hash(event) {
+            return Some(event.id.clone());
         }
-        
-        let total_score: f64 = products_map
-            .iter()
-            .map(|(product_id, _)| calculate_ethical_score(&product_id).unwrap_or(0.0))
-            .sum();
-        
-        total_score / products_map.len() as f64
-    });
+        previous_hash = event.hash.clone();
+    }
+    None
+}
 
-    AnalyticsData {
-        total_products,
-        active_shipments,
-        completed_deliveries,
-        average_ethical_score,
-        total_partners: PARTNERS.with(|partners| partners.borrow().len()),
-        total_users: USERS.with(|users| users.borrow().len()),
+// The product's current stage: its latest event that is not an amendment, as
+// corrected by any amendments made to it since
+fn get_last_product_event(product_id: &str) -> Option<SupplyChainEvent> {
+    let last = SUPPLY_CHAIN_EVENTS.with(|events| {
+        events.borrow()
+            .range(product_event_range(product_id))
+            .rev()
+            .map(|(_, event)| event)
+            .find(|event| !matches!(event.kind, Some(EventKind::Amendment { .. })))
+    })?;
+    latest_revision(last).ok()
+}
+
+fn get_event_by_id(event_id: &str) -> Result<SupplyChainEvent, String> {
+    let key = EVENT_IDS.with(|event_ids| event_ids.borrow().get(&event_id.to_string()))
+        .ok_or_else(|| "Event not found".to_string())?;
+    SUPPLY_CHAIN_EVENTS.with(|events| events.borrow().get(&key))
+        .ok_or_else(|| "Event not found".to_string())
+}
+
+fn get_amendment_id(event_id: &str) -> Option<String> {
+    EVENT_AMENDMENTS.with(|amendments| amendments.borrow().get(&event_id.to_string()))
+}
+
+// Follows the chain of amendments from `event` to its current revision
+fn latest_revision(mut event: SupplyChainEvent) -> Result<SupplyChainEvent, String> {
+    while let Some(amendment_id) = get_amendment_id(&event.id) {
+        event = get_event_by_id(&amendment_id)?;
     }
+    Ok(event)
 }
 
-// Utility Functions
-#[query]
-fn get_canister_status() -> CanisterStatus {
-    CanisterStatus {
-        version: "1.0.0".to_string(),
-        total_products: PRODUCTS.with(|products| products.borrow().len()),
-        total_users: USERS.with(|users| users.borrow().len()),
-        total_events: SUPPLY_CHAIN_EVENTS.with(|events| {
-            events.borrow()
-                .iter()
-                .map(|(_, event_list)| event_list.0.len() as u64)
-                .sum()
+fn mark_superseded_events(events: &mut [SupplyChainEvent]) {
+    for event in events {
+        event.superseded_by = get_amendment_id(&event.id);
+    }
+}
+
+fn allowed_next_stages(product: &Product) -> Vec<SupplyChainStage> {
+    if product.current_status == ProductStatus::Recalled {
+        return Vec::new();
+    }
+
+    match get_last_product_event(&product.id) {
+        Some(last_event) => TRANSITION_RULES.with(|rules| {
+            next_stages(rules.borrow().get(), &last_event.stage, &last_event.status)
         }),
-        uptime: time(),
+        None => vec![SupplyChainStage::RawMaterialSourcing],
     }
 }
 
-// Helper Functions
-fn get_user_by_principal(principal: &Principal) -> Result<User, String> {
-    USERS.with(|users| {
-        users.borrow().get(principal)
-            .ok_or_else(|| "User not found".to_string())
+fn validate_stage_transition(product: &Product, to_stage: &SupplyChainStage) -> Result<(), SupplyChainError> {
+    if product.current_status == ProductStatus::Recalled {
+        return Err(SupplyChainError::ProductRecalled { product_id: product.id.clone() });
+    }
+
+    let Some(last_event) = get_last_product_event(&product.id) else {
+        return Ok(());
+    };
+
+    let allowed = TRANSITION_RULES.with(|rules| {
+        next_stages(rules.borrow().get(), &last_event.stage, &last_event.status)
+    });
+
+    if allowed.contains(to_stage) {
+        Ok(())
+    } else {
+        Err(SupplyChainError::InvalidTransition {
+            product_id: product.id.clone(),
+            from: last_event.stage,
+            from_status: last_event.status,
+            to: to_stage.clone(),
+            allowed,
+        })
+    }
+}
+
+fn product_event_range(product_id: &str) -> RangeInclusive<Key<String, u64>> {
+    Key(product_id.to_string(), 0)..=Key(product_id.to_string(), u64::MAX)
+}
+
+// Returns the next value of the counter for `namespace`, starting at 1
+fn next_id_sequence(namespace: &str) -> u64 {
+    ID_COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+        let sequence = counters.get(&namespace.to_string()).unwrap_or(0) + 1;
+        counters.insert(namespace.to_string(), sequence);
+        sequence
     })
 }
 
-fn is_admin(principal: &Principal) -> Result<bool, String> {
-    let user = get_user_by_principal(principal)?;
-    Ok(user.role == UserRole::Admin)
+fn next_product_id(timestamp: u64) -> String {
+    let salt = ID_SALT.with(|salt| salt.borrow().get().clone());
+    loop {
+        let product_id = generate_product_id(timestamp, next_id_sequence("product"), &salt);
+        if !PRODUCTS.with(|products| products.borrow().contains_key(&product_id)) {
+            return product_id;
+        }
+    }
 }
 
-fn calculate_ethical_score(product_id: &String) -> Result<f64, String> {
-    let product = PRODUCTS.with(|products| {
-        products.borrow().get(product_id)
-            .ok_or_else(|| "Product not found".to_string())
-    })?;
+fn next_lot_id() -> String {
+    format!("LOT-{:06}", next_id_sequence("lot"))
+}
 
-    let events = SUPPLY_CHAIN_EVENTS.with(|events| {
-        events.borrow().get(product_id)
-            .map(|event_list| event_list.0)
-            .unwrap_or_default()
-    });
+fn next_event_id() -> String {
+    let salt = ID_SALT.with(|salt| salt.borrow().get().clone());
+    loop {
+        let event_id = generate_event_id(next_id_sequence("event"), &salt);
+        if !EVENT_IDS.with(|event_ids| event_ids.borrow().contains_key(&event_id)) {
+            return event_id;
+        }
+    }
+}
 
-    let mut score = product.sustainability_score.unwrap_or(50.0);
-    
-    // Add points for certifications
-    score += (product.certifications.len() as f64) * 5.0;
-    
-    // Add points for completed stages with certifications
-    for event in events {
-        if !event.certifications.is_empty() {
-            score += 2.0;
+const ID_SALT_RETRY_DELAY: Duration = Duration::from_secs(60);
+
+// raw_rand is an inter-canister call, so it cannot run inside init/post_upgrade
+fn schedule_id_salt_seeding() {
+    if ID_SALT.with(|salt| salt.borrow().get().is_empty()) {
+        ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(seed_id_salt()));
+    }
+}
+
+fn schedule_certification_scan() {
+    ic_cdk_timers::set_timer_interval(certifications::SCAN_INTERVAL, certifications::scan_certifications);
+}
+
+async fn seed_id_salt() {
+    match raw_rand().await {
+        Ok((bytes,)) => ID_SALT.with(|salt| {
+            salt.borrow_mut().set(bytes).expect("Failed to store ID salt");
+        }),
+        Err((code, message)) => {
+            ic_cdk::println!("Failed to seed ID salt, retrying: {:?} {}", code, message);
+            ic_cdk_timers::set_timer(ID_SALT_RETRY_DELAY, || ic_cdk::spawn(seed_id_salt()));
         }
     }
+}
+
+fn calculate_ethical_score(product_id: &str) -> Result<f64, String> {
+    Ok(ethical_score_breakdown(product_id, &active_scoring_policy())?.score)
+}
+
+fn ethical_score_breakdown(product_id: &str, policy: &ScoringPolicy) -> Result<EthicalScoreBreakdown, String> {
+    let product = get_product_by_id(product_id)?;
+    let mut events = get_product_events(product_id);
+    mark_superseded_events(&mut events);
+
+    Ok(scoring::score_breakdown(&product, &events, policy))
+}
+
+fn active_scoring_policy() -> ScoringPolicy {
+    SCORING_POLICIES.with(|policies| policies.borrow().last_key_value().map(|(_, policy)| policy))
+        .unwrap_or_else(default_scoring_policy)
+}
 
-    // Cap at 100
-    Ok(score.min(100.0))
+fn get_scoring_policy_by_version(version: u32) -> Result<ScoringPolicy, String> {
+    if version == 1 {
+        return Ok(default_scoring_policy());
+    }
+    SCORING_POLICIES.with(|policies| policies.borrow().get(&version))
+        .ok_or_else(|| "Scoring policy not found".to_string())
 }
 
 fn stage_to_product_status(stage: &SupplyChainStage) -> ProductStatus {
@@ -440,3 +3130,213 @@ fn stage_to_product_status(stage: &SupplyChainStage) -> ProductStatus {
 
 // Export candid interface
 ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(details: &[&str]) -> Vec<SupplyChainEvent> {
+        let mut previous_hash = GENESIS_HASH.to_string();
+        details.iter().enumerate().map(|(i, details)| {
+            let mut event = SupplyChainEvent {
+                id: format!("EVT-{:08X}", i),
+                product_id: "CT-2024-000001".to_string(),
+                stage: SupplyChainStage::Manufacturing,
+                location: "Porto".to_string(),
+                timestamp: i as u64,
+                actor: "Acme".to_string(),
+                actor_id: Principal::anonymous(),
+                status: EventStatus::Completed,
+                details: details.to_string(),
+                certifications: Vec::new(),
+                estimated_arrival: None,
+                metadata: std::collections::HashMap::new(),
+                kind: None,
+                lot_id: None,
+                previous_hash: previous_hash.clone(),
+                hash: String::new(),
+                superseded_by: None,
+            };
+            event.hash = compute_event_hash(&event);
+            previous_hash = event.hash.clone();
+            event
+        }).collect()
+    }
+
+    fn partner(partner_id: &str, manager_id: Principal) -> Partner {
+        Partner {
+            id: partner_id.to_string(),
+            manager_id,
+            company_name: "Acme Logistics".to_string(),
+            partner_type: PartnerType::LogisticsProvider,
+            contact_email: "ops@acme.test".to_string(),
+            contact_person: "Ops".to_string(),
+            certifications: Vec::new(),
+            status: PartnerStatus::Verified,
+            status_history: Vec::new(),
+            created_at: 0,
+            updated_at: 0,
+            reputation_score: 0,
+            certification_alerts: None,
+        }
+    }
+
+    fn product(product_id: &str, manufacturer_id: Principal) -> Product {
+        Product {
+            id: product_id.to_string(),
+            name: "Linen shirt".to_string(),
+            category: "Apparel".to_string(),
+            description: None,
+            manufacturer: "Acme".to_string(),
+            manufacturer_id,
+            batch_number: None,
+            production_date: 0,
+            raw_materials: Vec::new(),
+            certifications: Vec::new(),
+            sustainability_score: None,
+            estimated_value: None,
+            current_status: ProductStatus::Manufacturing,
+            current_location: "Porto".to_string(),
+            created_at: 0,
+            updated_at: 0,
+            custodian: None,
+            pending_handoff: None,
+            organisation_id: None,
+            certification_alerts: None,
+        }
+    }
+
+    fn stage_update(
+        product_id: &str,
+        actor_id: Principal,
+        stage: SupplyChainStage,
+        status: EventStatus,
+        timestamp: u64,
+        estimated_arrival: Option<u64>,
+    ) -> SupplyChainEvent {
+        SupplyChainEvent {
+            id: String::new(),
+            product_id: product_id.to_string(),
+            stage,
+            location: "Porto".to_string(),
+            timestamp,
+            actor: "Acme Logistics".to_string(),
+            actor_id,
+            status,
+            details: String::new(),
+            certifications: Vec::new(),
+            estimated_arrival,
+            metadata: std::collections::HashMap::new(),
+            kind: None,
+            lot_id: None,
+            previous_hash: String::new(),
+            hash: String::new(),
+            superseded_by: None,
+        }
+    }
+
+    fn amendment(original_id: &str, status: EventStatus, timestamp: u64, estimated_arrival: Option<u64>, location: &str) -> SupplyChainEvent {
+        let original = get_event_by_id(original_id).unwrap();
+        SupplyChainEvent {
+            id: next_event_id(),
+            location: location.to_string(),
+            timestamp,
+            status,
+            estimated_arrival,
+            kind: Some(EventKind::Amendment { amends: original.id.clone(), reason: "Misreported".to_string() }),
+            ..original
+        }
+    }
+
+    #[test]
+    fn amended_failure_is_recounted_and_corrects_the_product() {
+        let carrier = Principal::from_slice(&[1]);
+        store_partner(partner("PTR-000001", carrier));
+        store_product(product("CT-2024-000001", carrier));
+
+        let shipped = record_stage_event(
+            get_product_by_id("CT-2024-000001").unwrap(),
+            stage_update("CT-2024-000001", carrier, SupplyChainStage::Shipping, EventStatus::Failed, 100, Some(200)),
+        );
+        let distributed = record_stage_event(
+            get_product_by_id("CT-2024-000001").unwrap(),
+            stage_update("CT-2024-000001", carrier, SupplyChainStage::Distribution, EventStatus::Failed, 300, None),
+        );
+        let stats = reputation::stats("PTR-000001");
+        assert_eq!((stats.completed_events, stats.failed_events), (0, 2));
+        assert_eq!((stats.deliveries_with_eta, stats.on_time_deliveries), (1, 0));
+
+        // Not the current stage update: only the partner's record changes
+        record_amendment(
+            get_product_by_id("CT-2024-000001").unwrap(),
+            amendment(&shipped, EventStatus::Completed, 400, Some(350), "Porto"),
+        );
+        let stats = reputation::stats("PTR-000001");
+        assert_eq!(stats.events_recorded, 2);
+        assert_eq!((stats.completed_events, stats.failed_events), (1, 1));
+        assert_eq!((stats.deliveries_with_eta, stats.on_time_deliveries), (1, 1));
+        assert_eq!(get_product_by_id("CT-2024-000001").unwrap().updated_at, 300);
+
+        record_amendment(
+            get_product_by_id("CT-2024-000001").unwrap(),
+            amendment(&distributed, EventStatus::Completed, 500, None, "Lisbon"),
+        );
+        let stats = reputation::stats("PTR-000001");
+        assert_eq!((stats.completed_events, stats.failed_events), (2, 0));
+        assert_eq!((stats.deliveries_with_eta, stats.on_time_deliveries), (1, 1));
+
+        let product = get_product_by_id("CT-2024-000001").unwrap();
+        assert_eq!(product.current_status, ProductStatus::InTransit);
+        assert_eq!(product.current_location, "Lisbon");
+        assert_eq!(product.updated_at, 500);
+        assert_eq!(get_partner_by_id("PTR-000001").unwrap().reputation_score, reputation::breakdown("PTR-000001").score);
+    }
+
+    #[test]
+    fn hidden_products_are_reported_as_missing() {
+        let admin = Principal::from_slice(&[9]);
+        let stranger = Principal::from_slice(&[7]);
+        BOOTSTRAP_ADMINS.with(|admins| admins.borrow_mut().insert(admin, ()));
+        store_product(product("CT-2024-000001", Principal::from_slice(&[1])));
+        store_product_access("CT-2024-000001".to_string(), ProductAccess {
+            visibility: ProductVisibility::Private,
+            grants: Vec::new(),
+        });
+
+        let hidden = get_visible_product("CT-2024-000001", &stranger).unwrap_err();
+        let missing = get_visible_product("CT-2024-999999", &stranger).unwrap_err();
+        assert_eq!(hidden, missing);
+        assert!(get_visible_product("CT-2024-000001", &admin).is_ok());
+    }
+
+    #[test]
+    fn intact_chain_verifies() {
+        assert_eq!(verify_event_chain(&chain(&["cut", "sewn", "checked"])), None);
+        assert_eq!(verify_event_chain(&[]), None);
+    }
+
+    #[test]
+    fn edited_event_is_reported() {
+        let mut events = chain(&["cut", "sewn", "checked"]);
+        events[1].details = "sewn twice".to_string();
+
+        assert_eq!(verify_event_chain(&events), Some(events[1].id.clone()));
+    }
+
+    #[test]
+    fn rehashed_edit_breaks_the_next_link() {
+        let mut events = chain(&["cut", "sewn", "checked"]);
+        events[1].details = "sewn twice".to_string();
+        events[1].hash = compute_event_hash(&events[1]);
+
+        assert_eq!(verify_event_chain(&events), Some(events[2].id.clone()));
+    }
+
+    #[test]
+    fn removed_event_is_reported() {
+        let mut events = chain(&["cut", "sewn", "checked"]);
+        events.remove(0);
+
+        assert_eq!(verify_event_chain(&events), Some(events[0].id.clone()));
+    }
+}
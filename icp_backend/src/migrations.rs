@@ -0,0 +1,432 @@
+use crate::{active_scoring_policy, index_lot_products, index_partner_manager, next_id_sequence, EVENT_ACTORS, EVENT_IDS, LAST_STAGE_UPDATES, LEGACY_PARTNERS, LEGACY_SUPPLY_CHAIN_EVENTS, LOTS, ORGANISATIONS, ORGANISATION_NAMES, PARTNERS, PARTNER_RATINGS, PARTNER_REPUTATION, PRODUCTS, PRODUCT_HANDLERS, RESTRICTED_TEXT_INDEX, SCORING_POLICIES, STORAGE_VERSION, SUPPLY_CHAIN_EVENTS, TEXT_INDEX, USERS};
+use crate::certifications;
+use crate::index::reindex_product;
+use crate::storage::Key;
+use crate::reputation;
+use crate::text_index;
+use crate::types::{
+    Lot, Organisation, OrganisationMember, OrganisationRole, Partner, PartnerStatus, PartnerStatusChange, PartnerV1, Product,
+    SupplyChainEvent, SupplyChainEventList, User,
+};
+use crate::utils::{compute_event_hash, normalize_company_name, GENESIS_HASH};
+use candid::Principal;
+use ic_stable_structures::{Memory, StableBTreeMap, Storable};
+use std::collections::BTreeMap;
+
+// Layout version of the data in stable memory. Bump it and add a step to
+// `run_migrations` whenever stored data has to be rewritten on upgrade.
+pub const CURRENT_STORAGE_VERSION: u32 = 17;
+
+pub fn run_migrations() {
+    let stored_version = STORAGE_VERSION.with(|version| *version.borrow().get());
+
+    if stored_version < 1 {
+        migrate_to_versioned_records();
+    }
+
+    if stored_version < 2 {
+        migrate_to_per_event_storage();
+    }
+
+    if stored_version < 3 {
+        index_event_ids();
+    }
+
+    if stored_version < 4 {
+        build_event_hash_chains();
+    }
+
+    if stored_version < 5 {
+        build_product_indexes();
+    }
+
+    if stored_version < 6 {
+        build_text_index();
+    }
+
+    if stored_version < 7 {
+        group_users_into_organisations();
+    }
+
+    if stored_version < 8 {
+        migrate_partners_to_own_ids();
+    }
+
+    if stored_version < 9 {
+        compute_partner_reputation();
+    }
+
+    if stored_version < 10 {
+        split_restricted_text_index();
+    }
+
+    if stored_version < 11 {
+        recompute_partner_reputation();
+    }
+
+    if stored_version < 12 {
+        publish_lapsed_certification_policy();
+    }
+
+    if stored_version < 13 {
+        // Event hashes now encode stages, statuses and kinds as explicit tags
+        build_event_hash_chains();
+    }
+
+    if stored_version < 14 {
+        build_lot_product_index();
+    }
+
+    if stored_version < 15 {
+        index_partner_activity();
+    }
+
+    if stored_version < 16 {
+        index_event_actors();
+    }
+
+    if stored_version < 17 {
+        index_certification_references();
+    }
+
+    set_storage_version(CURRENT_STORAGE_VERSION);
+}
+
+pub fn set_storage_version(version: u32) {
+    STORAGE_VERSION.with(|stored| {
+        stored.borrow_mut()
+            .set(version)
+            .expect("Failed to write storage version");
+    });
+}
+
+// Version 0 -> 1: records in memories 0-3 were bare Candid with bounded sizes.
+// The maps load fine with unbounded values, so rewriting every entry is enough
+// to move them into the versioned envelope.
+fn migrate_to_versioned_records() {
+    PRODUCTS.with(|products| rewrite_entries(&mut products.borrow_mut()));
+    USERS.with(|users| rewrite_entries(&mut users.borrow_mut()));
+    LEGACY_SUPPLY_CHAIN_EVENTS.with(|events| rewrite_entries(&mut events.borrow_mut()));
+    LEGACY_PARTNERS.with(|partners| rewrite_entries(&mut partners.borrow_mut()));
+}
+
+// Version 1 -> 2: each product's history moves out of the single list in memory 2
+// into one entry per event keyed by (product_id, sequence) in memory 6.
+fn migrate_to_per_event_storage() {
+    let histories: Vec<(String, SupplyChainEventList)> =
+        LEGACY_SUPPLY_CHAIN_EVENTS.with(|events| events.borrow().iter().collect());
+
+    SUPPLY_CHAIN_EVENTS.with(|events| {
+        let mut events_map = events.borrow_mut();
+        for (product_id, event_list) in histories {
+            for (sequence, event) in event_list.0.into_iter().enumerate() {
+                events_map.insert(Key(product_id.clone(), sequence as u64), event.into());
+            }
+        }
+    });
+
+    LEGACY_SUPPLY_CHAIN_EVENTS.with(|events| events.borrow_mut().clear_new());
+}
+
+// Version 2 -> 3: event IDs get an index for uniqueness checks. The old generator
+// could hand out the same ID twice; the earliest event keeps the index entry.
+fn index_event_ids() {
+    let keys: Vec<(String, Key<String, u64>)> = SUPPLY_CHAIN_EVENTS.with(|events| {
+        events.borrow()
+            .iter()
+            .map(|(key, event)| (event.id, key))
+            .collect()
+    });
+
+    EVENT_IDS.with(|event_ids| {
+        let mut event_ids = event_ids.borrow_mut();
+        for (event_id, key) in keys {
+            if !event_ids.contains_key(&event_id) {
+                event_ids.insert(event_id, key);
+            }
+        }
+    });
+}
+
+// Version 3 -> 4: events gain previous_hash/hash. Histories are walked in
+// sequence order and chained as if each event had just been appended.
+// Version 12 -> 13 runs it again to rehash events under the tagged encoding.
+fn build_event_hash_chains() {
+    SUPPLY_CHAIN_EVENTS.with(|events| {
+        let mut events_map = events.borrow_mut();
+        let entries: Vec<(Key<String, u64>, SupplyChainEvent)> = events_map.iter().collect();
+
+        let mut previous: Option<(String, String)> = None;
+        for (key, mut event) in entries {
+            event.previous_hash = match &previous {
+                Some((product_id, hash)) if *product_id == key.0 => hash.clone(),
+                _ => GENESIS_HASH.to_string(),
+            };
+            event.hash = compute_event_hash(&event);
+            previous = Some((key.0.clone(), event.hash.clone()));
+            events_map.insert(key, event);
+        }
+    });
+}
+
+// Version 4 -> 5: secondary indexes for search_products
+fn build_product_indexes() {
+    let products: Vec<Product> = PRODUCTS.with(|products| {
+        products.borrow().iter().map(|(_, product)| product).collect()
+    });
+
+    for product in &products {
+        reindex_product(None, product);
+    }
+}
+
+// Version 5 -> 6: full-text index over product fields and event details
+fn build_text_index() {
+    let products: Vec<Product> = PRODUCTS.with(|products| {
+        products.borrow().iter().map(|(_, product)| product).collect()
+    });
+    for product in &products {
+        text_index::reindex_product(None, product);
+    }
+
+    SUPPLY_CHAIN_EVENTS.with(|events| {
+        for (_, event) in events.borrow().iter() {
+            text_index::index_event(&event);
+        }
+    });
+}
+
+// Version 6 -> 7: users with the same normalised company name become members of
+// one organisation, with the earliest registered user as owner. Products move to
+// their manufacturer's organisation.
+fn group_users_into_organisations() {
+    let mut users: Vec<User> = USERS.with(|users| {
+        users.borrow().iter().map(|(_, user)| user).collect()
+    });
+    users.sort_by_key(|user| user.created_at);
+
+    let mut organisations: BTreeMap<String, Organisation> = BTreeMap::new();
+    for user in &mut users {
+        let normalized_name = normalize_company_name(&user.company);
+        if normalized_name.is_empty() || user.organisation_id.is_some() {
+            continue;
+        }
+
+        let organisation = organisations.entry(normalized_name).or_insert_with(|| Organisation {
+            id: format!("ORG-{:06}", next_id_sequence("organisation")),
+            name: user.company.trim().to_string(),
+            members: Vec::new(),
+            created_at: user.created_at,
+        });
+        let role = if organisation.members.is_empty() {
+            OrganisationRole::Owner
+        } else {
+            OrganisationRole::Member
+        };
+        organisation.members.push(OrganisationMember {
+            user_id: user.id,
+            role,
+            joined_at: user.created_at,
+        });
+        user.organisation_id = Some(organisation.id.clone());
+    }
+
+    for (normalized_name, organisation) in organisations {
+        ORGANISATION_NAMES.with(|names| names.borrow_mut().insert(normalized_name, organisation.id.clone()));
+        ORGANISATIONS.with(|stored| stored.borrow_mut().insert(organisation.id.clone(), organisation));
+    }
+
+    let user_organisations: BTreeMap<Principal, String> = users.iter()
+        .filter_map(|user| Some((user.id, user.organisation_id.clone()?)))
+        .collect();
+    USERS.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        for user in users {
+            stored.insert(user.id, user);
+        }
+    });
+
+    let products: Vec<Product> = PRODUCTS.with(|products| {
+        products.borrow().iter().map(|(_, product)| product).collect()
+    });
+    for previous in products {
+        let Some(organisation_id) = user_organisations.get(&previous.manufacturer_id) else {
+            continue;
+        };
+        let mut product = previous.clone();
+        product.organisation_id = Some(organisation_id.clone());
+        PRODUCTS.with(|stored| stored.borrow_mut().insert(product.id.clone(), product.clone()));
+        reindex_product(Some(&previous), &product);
+    }
+}
+
+// Version 7 -> 8: partners keyed by the registering principal in memory 3 get
+// their own IDs in memory 20, with that principal as manager. Verified partners
+// stay verified; the registration starts their status history.
+fn migrate_partners_to_own_ids() {
+    let legacy_partners: Vec<PartnerV1> = LEGACY_PARTNERS.with(|partners| {
+        partners.borrow().iter().map(|(_, partner)| partner).collect()
+    });
+
+    for legacy in legacy_partners {
+        let status = if legacy.verified { PartnerStatus::Verified } else { PartnerStatus::Pending };
+        let partner = Partner {
+            id: format!("PTR-{:06}", next_id_sequence("partner")),
+            manager_id: legacy.id,
+            company_name: legacy.company_name,
+            partner_type: legacy.partner_type,
+            contact_email: legacy.contact_email,
+            contact_person: legacy.contact_person,
+            certifications: legacy.certifications,
+            status: status.clone(),
+            status_history: vec![PartnerStatusChange {
+                status,
+                changed_by: legacy.id,
+                changed_at: legacy.created_at,
+                reason: None,
+            }],
+            created_at: legacy.created_at,
+            updated_at: legacy.created_at,
+            reputation_score: legacy.reputation_score,
+            certification_alerts: None,
+        };
+        PARTNERS.with(|partners| partners.borrow_mut().insert(partner.id.clone(), partner));
+    }
+
+    LEGACY_PARTNERS.with(|partners| partners.borrow_mut().clear_new());
+}
+
+// Version 8 -> 9: replays every product history through the reputation engine,
+// which from now on is updated as events are appended. The per-product state the
+// engine keeps is rebuilt along the way.
+fn compute_partner_reputation() {
+    LAST_STAGE_UPDATES.with(|updates| updates.borrow_mut().clear_new());
+    PRODUCT_HANDLERS.with(|handlers| handlers.borrow_mut().clear_new());
+
+    let events: Vec<SupplyChainEvent> = SUPPLY_CHAIN_EVENTS.with(|events| {
+        events.borrow().iter().map(|(_, event)| event).collect()
+    });
+
+    for event in &events {
+        reputation::record_event(event);
+    }
+}
+
+// Version 9 -> 10: event metadata tokens move out of TEXT_INDEX into
+// RESTRICTED_TEXT_INDEX, so both are rebuilt from scratch
+fn split_restricted_text_index() {
+    TEXT_INDEX.with(|index| index.borrow_mut().clear_new());
+    RESTRICTED_TEXT_INDEX.with(|index| index.borrow_mut().clear_new());
+    build_text_index();
+}
+
+// Version 10 -> 11: events used to be credited to partners by company name as
+// well. Reputation is rebuilt with attribution by principal; stored ratings are
+// replayed as they are.
+fn recompute_partner_reputation() {
+    PARTNER_REPUTATION.with(|reputation| reputation.borrow_mut().clear_new());
+
+    let partners: Vec<Partner> = PARTNERS.with(|partners| {
+        partners.borrow().iter().map(|(_, partner)| partner).collect()
+    });
+    for mut partner in partners {
+        partner.reputation_score = reputation::breakdown(&partner.id).score;
+        PARTNERS.with(|partners| partners.borrow_mut().insert(partner.id.clone(), partner));
+    }
+
+    compute_partner_reputation();
+
+    let ratings: Vec<(String, u8)> = PARTNER_RATINGS.with(|ratings| {
+        ratings.borrow().iter().map(|(Key(partner_id, _), rating)| (partner_id, rating)).collect()
+    });
+    for (partner_id, rating) in ratings {
+        if let Some(partner) = PARTNERS.with(|partners| partners.borrow().get(&partner_id)) {
+            reputation::record_rating(partner, None, rating);
+        }
+    }
+}
+
+// Version 11 -> 12: earlier policies score lapsed certifications like valid
+// ones. The active policy is republished as a new version in which they earn
+// nothing, leaving the parameters of earlier versions unchanged. Also run on
+// install so new canisters start from the same policy.
+pub fn publish_lapsed_certification_policy() {
+    let mut policy = active_scoring_policy();
+    if policy.lapsed_certification_points.is_some() {
+        return;
+    }
+    policy.version += 1;
+    policy.lapsed_certification_points = Some(0.0);
+    policy.created_by = ic_cdk::api::id();
+    policy.created_at = ic_cdk::api::time();
+
+    SCORING_POLICIES.with(|policies| policies.borrow_mut().insert(policy.version, policy));
+}
+
+// Version 13 -> 14: lot custody transfers look up the active lots of a product
+// through PRODUCT_LOTS instead of scanning every lot
+fn build_lot_product_index() {
+    let lots: Vec<Lot> = LOTS.with(|lots| lots.borrow().iter().map(|(_, lot)| lot).collect());
+    for lot in &lots {
+        index_lot_products(None, lot);
+    }
+}
+
+// Version 14 -> 15: the reputation engine finds partners through their
+// managers and keeps each product's latest stage update and handlers instead of
+// reloading its history. Reputation is recomputed to fill that state in.
+fn index_partner_activity() {
+    let partners: Vec<Partner> = PARTNERS.with(|partners| {
+        partners.borrow().iter().map(|(_, partner)| partner).collect()
+    });
+    for partner in &partners {
+        index_partner_manager(partner);
+    }
+
+    recompute_partner_reputation();
+}
+
+// Version 15 -> 16: access checks find out whether the caller recorded an event
+// of the product through EVENT_ACTORS instead of loading its history
+fn index_event_actors() {
+    let actors: Vec<Key<String, Principal>> = SUPPLY_CHAIN_EVENTS.with(|events| {
+        events.borrow().iter().map(|(Key(product_id, _), event)| Key(product_id, event.actor_id)).collect()
+    });
+
+    EVENT_ACTORS.with(|index| {
+        let mut index = index.borrow_mut();
+        for actor in actors {
+            index.insert(actor, ());
+        }
+    });
+}
+
+// Version 16 -> 17: the certification scan finds the products and partners
+// referencing a certification through their reference indexes
+fn index_certification_references() {
+    let products: Vec<Product> = PRODUCTS.with(|products| {
+        products.borrow().iter().map(|(_, product)| product).collect()
+    });
+    for product in &products {
+        certifications::reindex_product_references(None, product);
+    }
+
+    let partners: Vec<Partner> = PARTNERS.with(|partners| {
+        partners.borrow().iter().map(|(_, partner)| partner).collect()
+    });
+    for partner in &partners {
+        certifications::reindex_partner_references(None, partner);
+    }
+}
+
+fn rewrite_entries<K, V, M>(map: &mut StableBTreeMap<K, V, M>)
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+    M: Memory,
+{
+    let entries: Vec<(K, V)> = map.iter().collect();
+    for (key, value) in entries {
+        map.insert(key, value);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::types::Page;
+use crate::utils::{from_hex, to_hex};
+
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 500;
+
+pub fn page_size(limit: Option<u32>) -> usize {
+    limit.map_or(DEFAULT_PAGE_SIZE, |limit| (limit as usize).clamp(1, MAX_PAGE_SIZE))
+}
+
+// Cursors are the hex-encoded position of the last item on the previous page.
+// Clients treat them as opaque; only this module knows the encoding.
+pub fn encode_cursor(position: &str) -> String {
+    to_hex(position.as_bytes())
+}
+
+pub fn decode_cursor(cursor: &str) -> Result<String, String> {
+    from_hex(cursor)
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or_else(|| "Invalid cursor".to_string())
+}
+
+// For cursors over numeric keys: the key the next page starts at. A cursor at
+// u64::MAX cannot be followed, so it is rejected rather than wrapped.
+pub fn decode_sequence_cursor(cursor: &str) -> Result<u64, String> {
+    decode_cursor(cursor)?
+        .parse::<u64>()
+        .ok()
+        .and_then(|position| position.checked_add(1))
+        .ok_or_else(|| "Invalid cursor".to_string())
+}
+
+// Takes up to `limit` entries that follow the cursor position. `position` maps
+// an entry's key to the position a continuation cursor resumes after.
+pub fn collect_page<K, T>(
+    entries: impl Iterator<Item = (K, T)>,
+    limit: usize,
+    total_count_hint: Option<u64>,
+    position: impl Fn(&K) -> String,
+) -> Page<T> {
+    let mut entries = entries.peekable();
+    let mut items = Vec::new();
+    let mut last_key = None;
+
+    while items.len() < limit {
+        match entries.next() {
+            Some((key, item)) => {
+                items.push(item);
+                last_key = Some(key);
+            }
+            None => break,
+        }
+    }
+
+    let next_cursor = match (entries.peek(), last_key) {
+        (Some(_), Some(key)) => Some(encode_cursor(&position(&key))),
+        _ => None,
+    };
+
+    Page {
+        items,
+        next_cursor,
+        total_count_hint,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cursor_round_trips() {
+        let cursor = encode_cursor("42:CT-2024-00A1B2");
+
+        assert_eq!(cursor, "34323a43542d323032342d303041314232");
+        assert_eq!(decode_cursor(&cursor).unwrap(), "42:CT-2024-00A1B2");
+    }
+
+    #[test]
+    fn malformed_cursors_are_rejected() {
+        for cursor in ["3", "zz", "ff", "c328"] {
+            assert_eq!(decode_cursor(cursor), Err("Invalid cursor".to_string()), "{}", cursor);
+        }
+    }
+
+    #[test]
+    fn sequence_cursor_resumes_after_position() {
+        assert_eq!(decode_sequence_cursor(&encode_cursor("0")), Ok(1));
+        assert_eq!(decode_sequence_cursor(&encode_cursor("41")), Ok(42));
+    }
+
+    #[test]
+    fn sequence_cursor_rejects_overflow_and_non_numbers() {
+        let rejected = [u64::MAX.to_string(), "18446744073709551616".to_string(), "-1".to_string(), "abc".to_string()];
+        for position in rejected {
+            assert_eq!(decode_sequence_cursor(&encode_cursor(&position)), Err("Invalid cursor".to_string()), "{}", position);
+        }
+    }
+
+    #[test]
+    fn page_cursor_points_at_last_item_only_when_more_follow() {
+        let entries = || (1..=5u64).map(|sequence| (sequence, sequence * 10));
+
+        let first = collect_page(entries(), 3, None, |sequence| sequence.to_string());
+        assert_eq!(first.items, vec![10, 20, 30]);
+        assert_eq!(first.next_cursor.as_deref().map(decode_sequence_cursor), Some(Ok(4)));
+
+        let last = collect_page(entries().skip(3), 3, None, |sequence| sequence.to_string());
+        assert_eq!(last.items, vec![40, 50]);
+        assert_eq!(last.next_cursor, None);
+    }
+}
@@ -0,0 +1,554 @@
+// Minimal QR code encoder (ISO/IEC 18004): byte mode, error correction level M,
+// versions 1-10, which holds URLs of up to 213 bytes. Structure follows Project
+// Nayuki's reference implementation.
+
+const MAX_VERSION: usize = 10;
+
+// Level M parameters indexed by version (index 0 unused)
+const ECC_CODEWORDS_PER_BLOCK: [usize; MAX_VERSION + 1] = [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26];
+const NUM_ERROR_CORRECTION_BLOCKS: [usize; MAX_VERSION + 1] = [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5];
+
+// Format information bits identifying level M
+const ECC_LEVEL_M_FORMAT_BITS: u32 = 0;
+
+// Quiet zone around the symbol, in modules
+const QUIET_ZONE: usize = 4;
+
+pub struct QrCode {
+    size: usize,
+    modules: Vec<bool>,
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    pub fn encode(data: &[u8]) -> Result<QrCode, String> {
+        let version = (1..=MAX_VERSION)
+            .find(|&version| data_bit_length(version, data.len()) <= num_data_codewords(version) * 8)
+            .ok_or_else(|| format!("QR code payload too long: {} bytes", data.len()))?;
+
+        let codewords = add_ecc_and_interleave(version, &encode_data_codewords(version, data));
+
+        let size = version * 4 + 17;
+        let mut qr = QrCode {
+            size,
+            modules: vec![false; size * size],
+            is_function: vec![false; size * size],
+        };
+        qr.draw_function_patterns(version);
+        qr.draw_codewords(&codewords);
+
+        // Keep the mask with the lowest penalty score
+        let mut best_mask = 0;
+        let mut best_penalty = u32::MAX;
+        for mask in 0..8 {
+            qr.apply_mask(mask);
+            qr.draw_format_bits(mask);
+            let penalty = qr.penalty_score();
+            if penalty < best_penalty {
+                best_mask = mask;
+                best_penalty = penalty;
+            }
+            qr.apply_mask(mask);
+        }
+        qr.apply_mask(best_mask);
+        qr.draw_format_bits(best_mask);
+
+        Ok(qr)
+    }
+
+    // Width of the symbol including the quiet zone
+    pub fn width(&self) -> usize {
+        self.size + QUIET_ZONE * 2
+    }
+
+    // SVG path data for the dark modules, one unit per module, quiet zone included
+    pub fn svg_path(&self) -> String {
+        let mut path = String::new();
+        for y in 0..self.size {
+            for x in 0..self.size {
+                if self.module(x, y) {
+                    path.push_str(&format!("M{},{}h1v1h-1z", x + QUIET_ZONE, y + QUIET_ZONE));
+                }
+            }
+        }
+        path
+    }
+
+    pub fn to_svg(&self) -> String {
+        let width = self.width();
+        format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"0 0 {0} {0}\" shape-rendering=\"crispEdges\">\
+             <rect width=\"{0}\" height=\"{0}\" fill=\"#ffffff\"/>\
+             <path d=\"{1}\" fill=\"#000000\"/></svg>",
+            width,
+            self.svg_path()
+        )
+    }
+
+    fn module(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.size + x]
+    }
+
+    fn set_function_module(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.size + x] = dark;
+        self.is_function[y * self.size + x] = true;
+    }
+
+    fn draw_function_patterns(&mut self, version: usize) {
+        for i in 0..self.size {
+            self.set_function_module(6, i, i % 2 == 0);
+            self.set_function_module(i, 6, i % 2 == 0);
+        }
+
+        self.draw_finder_pattern(3, 3);
+        self.draw_finder_pattern(self.size as i32 - 4, 3);
+        self.draw_finder_pattern(3, self.size as i32 - 4);
+
+        let positions = alignment_pattern_positions(version);
+        let last = positions.len().saturating_sub(1);
+        for (i, &x) in positions.iter().enumerate() {
+            for (j, &y) in positions.iter().enumerate() {
+                // Skip the three corners occupied by finder patterns
+                let finder_corner = (i == 0 && (j == 0 || j == last)) || (i == last && j == 0);
+                if !finder_corner {
+                    self.draw_alignment_pattern(x, y);
+                }
+            }
+        }
+
+        // Reserve the format areas now; the real bits are drawn once the mask is known
+        self.draw_format_bits(0);
+        self.draw_version(version);
+    }
+
+    // Finder pattern plus its separator, centred on (x, y)
+    fn draw_finder_pattern(&mut self, x: i32, y: i32) {
+        for dy in -4..=4 {
+            for dx in -4..=4 {
+                let (xx, yy) = (x + dx, y + dy);
+                if (0..self.size as i32).contains(&xx) && (0..self.size as i32).contains(&yy) {
+                    let distance = dx.abs().max(dy.abs());
+                    self.set_function_module(xx as usize, yy as usize, distance != 2 && distance != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment_pattern(&mut self, x: usize, y: usize) {
+        for dy in -2i32..=2 {
+            for dx in -2i32..=2 {
+                let distance = dx.abs().max(dy.abs());
+                self.set_function_module((x as i32 + dx) as usize, (y as i32 + dy) as usize, distance != 1);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u32) {
+        let bits = format_bits(ECC_LEVEL_M_FORMAT_BITS, mask);
+        let bit = |i: usize| (bits >> i) & 1 != 0;
+
+        // First copy, around the top-left finder pattern
+        for i in 0..6 {
+            self.set_function_module(8, i, bit(i));
+        }
+        self.set_function_module(8, 7, bit(6));
+        self.set_function_module(8, 8, bit(7));
+        self.set_function_module(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function_module(14 - i, 8, bit(i));
+        }
+
+        // Second copy, split between the other two finder patterns
+        let size = self.size;
+        for i in 0..8 {
+            self.set_function_module(size - 1 - i, 8, bit(i));
+        }
+        for i in 8..15 {
+            self.set_function_module(8, size - 15 + i, bit(i));
+        }
+        self.set_function_module(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self, version: usize) {
+        if version < 7 {
+            return;
+        }
+
+        let mut remainder = version as u32;
+        for _ in 0..12 {
+            remainder = (remainder << 1) ^ ((remainder >> 11) * 0x1F25);
+        }
+        let bits = ((version as u32) << 12) | remainder;
+
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let a = self.size - 11 + i % 3;
+            let b = i / 3;
+            self.set_function_module(a, b, dark);
+            self.set_function_module(b, a, dark);
+        }
+    }
+
+    // Places codeword bits in the zigzag order, two columns at a time from the right
+    fn draw_codewords(&mut self, codewords: &[u8]) {
+        let total_bits = codewords.len() * 8;
+        let mut i = 0;
+        let mut right = self.size as i32 - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vertical in 0..self.size {
+                for j in 0..2 {
+                    let x = (right - j) as usize;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { self.size - 1 - vertical } else { vertical };
+                    if !self.is_function[y * self.size + x] && i < total_bits {
+                        self.modules[y * self.size + x] = (codewords[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    // XORs the mask pattern over all non-function modules; applying it twice undoes it
+    fn apply_mask(&mut self, mask: u32) {
+        for y in 0..self.size {
+            for x in 0..self.size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = y * self.size + x;
+                if invert && !self.is_function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    fn penalty_score(&self) -> u32 {
+        let size = self.size;
+        let mut penalty = 0;
+
+        // Runs of five or more same-coloured modules in rows and columns
+        for transpose in [false, true] {
+            for a in 0..size {
+                let mut run_color = false;
+                let mut run_length = 0;
+                for b in 0..size {
+                    let dark = if transpose { self.module(a, b) } else { self.module(b, a) };
+                    if b > 0 && dark == run_color {
+                        run_length += 1;
+                    } else {
+                        run_color = dark;
+                        run_length = 1;
+                    }
+                    if run_length == 5 {
+                        penalty += 3;
+                    } else if run_length > 5 {
+                        penalty += 1;
+                    }
+                }
+            }
+        }
+
+        // 2x2 blocks of the same colour
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let color = self.module(x, y);
+                if color == self.module(x + 1, y)
+                    && color == self.module(x, y + 1)
+                    && color == self.module(x + 1, y + 1)
+                {
+                    penalty += 3;
+                }
+            }
+        }
+
+        // Finder-like 1:1:3:1:1 patterns with four light modules on either side
+        const PATTERN: [bool; 11] = [true, false, true, true, true, false, true, false, false, false, false];
+        for transpose in [false, true] {
+            for a in 0..size {
+                for start in 0..=size - PATTERN.len() {
+                    let at = |k: usize| if transpose { self.module(a, start + k) } else { self.module(start + k, a) };
+                    let forward = (0..PATTERN.len()).all(|k| at(k) == PATTERN[k]);
+                    let backward = (0..PATTERN.len()).all(|k| at(k) == PATTERN[PATTERN.len() - 1 - k]);
+                    if forward || backward {
+                        penalty += 40;
+                    }
+                }
+            }
+        }
+
+        // Balance of dark and light modules
+        let dark = self.modules.iter().filter(|&&dark| dark).count();
+        let total = size * size;
+        let deviation = (dark * 100 / total).abs_diff(50);
+        penalty + (deviation / 5) as u32 * 10
+    }
+}
+
+// 15-bit format information: error correction level and mask, BCH(15,5)
+// protected and XORed with the fixed pattern 0x5412
+fn format_bits(ecc_level_bits: u32, mask: u32) -> u32 {
+    let data = (ecc_level_bits << 3) | mask;
+    let mut remainder = data;
+    for _ in 0..10 {
+        remainder = (remainder << 1) ^ ((remainder >> 9) * 0x537);
+    }
+    ((data << 10) | remainder) ^ 0x5412
+}
+
+fn char_count_bits(version: usize) -> usize {
+    if version <= 9 { 8 } else { 16 }
+}
+
+fn data_bit_length(version: usize, data_len: usize) -> usize {
+    4 + char_count_bits(version) + data_len * 8
+}
+
+fn num_raw_data_modules(version: usize) -> usize {
+    let mut result = (16 * version + 128) * version + 64;
+    if version >= 2 {
+        let num_align = version / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: usize) -> usize {
+    num_raw_data_modules(version) / 8
+        - ECC_CODEWORDS_PER_BLOCK[version] * NUM_ERROR_CORRECTION_BLOCKS[version]
+}
+
+fn alignment_pattern_positions(version: usize) -> Vec<usize> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let size = version * 4 + 17;
+    let num_align = version / 7 + 2;
+    let step = (version * 4 + num_align * 2 + 1) / (num_align * 2 - 2) * 2;
+    let mut positions: Vec<usize> = (0..num_align - 1).map(|i| size - 7 - i * step).collect();
+    positions.push(6);
+    positions.reverse();
+    positions
+}
+
+// Byte-mode segment, terminator and padding up to the version's data capacity
+fn encode_data_codewords(version: usize, data: &[u8]) -> Vec<u8> {
+    let capacity_bits = num_data_codewords(version) * 8;
+    let mut bits: Vec<bool> = Vec::with_capacity(capacity_bits);
+    let push_bits = |value: u32, count: usize, bits: &mut Vec<bool>| {
+        for i in (0..count).rev() {
+            bits.push((value >> i) & 1 != 0);
+        }
+    };
+
+    push_bits(0b0100, 4, &mut bits);
+    push_bits(data.len() as u32, char_count_bits(version), &mut bits);
+    for &byte in data {
+        push_bits(byte as u32, 8, &mut bits);
+    }
+
+    let terminator = (capacity_bits - bits.len()).min(4);
+    push_bits(0, terminator, &mut bits);
+    let padding = (8 - bits.len() % 8) % 8;
+    push_bits(0, padding, &mut bits);
+
+    let mut codewords: Vec<u8> = bits
+        .chunks(8)
+        .map(|chunk| chunk.iter().fold(0u8, |acc, &bit| (acc << 1) | bit as u8))
+        .collect();
+    for pad in [0xEC, 0x11].iter().cycle() {
+        if codewords.len() * 8 >= capacity_bits {
+            break;
+        }
+        codewords.push(*pad);
+    }
+    codewords
+}
+
+// Splits data into blocks, appends Reed-Solomon ECC and interleaves the result
+fn add_ecc_and_interleave(version: usize, data: &[u8]) -> Vec<u8> {
+    let num_blocks = NUM_ERROR_CORRECTION_BLOCKS[version];
+    let block_ecc_len = ECC_CODEWORDS_PER_BLOCK[version];
+    let raw_codewords = num_raw_data_modules(version) / 8;
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+
+    let divisor = reed_solomon_divisor(block_ecc_len);
+    let mut blocks: Vec<Vec<u8>> = Vec::with_capacity(num_blocks);
+    let mut offset = 0;
+    for i in 0..num_blocks {
+        let data_len = short_block_len - block_ecc_len + usize::from(i >= num_short_blocks);
+        let mut block = data[offset..offset + data_len].to_vec();
+        offset += data_len;
+        let ecc = reed_solomon_remainder(&block, &divisor);
+        if i < num_short_blocks {
+            // Placeholder so every block has the same length while interleaving
+            block.push(0);
+        }
+        block.extend(ecc);
+        blocks.push(block);
+    }
+
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_len - block_ecc_len || j >= num_short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = gf_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = gf_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for &byte in data {
+        let factor = byte ^ result.remove(0);
+        result.push(0);
+        for (value, &coefficient) in result.iter_mut().zip(divisor) {
+            *value ^= gf_multiply(coefficient, factor);
+        }
+    }
+    result
+}
+
+// Multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1
+fn gf_multiply(x: u8, y: u8) -> u8 {
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reed_solomon_remainder_matches_reference_messages() {
+        let divisor = reed_solomon_divisor(10);
+
+        // "HELLO WORLD" as version 1-M alphanumeric data
+        let hello_world = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        assert_eq!(
+            reed_solomon_remainder(&hello_world, &divisor),
+            vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+
+        // "01234567" as version 1-M numeric data, the example in ISO/IEC 18004 Annex I
+        let numeric = [
+            0x10, 0x20, 0x0C, 0x56, 0x61, 0x80, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11, 0xEC, 0x11,
+        ];
+        assert_eq!(
+            reed_solomon_remainder(&numeric, &divisor),
+            vec![0xA5, 0x24, 0xD4, 0xC1, 0xED, 0x36, 0xC7, 0x87, 0x2C, 0x55]
+        );
+    }
+
+    #[test]
+    fn format_bits_match_the_standard_table() {
+        // (level bits, format strings for masks 0-7) for L, M, Q and H
+        let table: [(u32, [u32; 8]); 4] = [
+            (0b01, [
+                0b111011111000100, 0b111001011110011, 0b111110110101010, 0b111100010011101,
+                0b110011000101111, 0b110001100011000, 0b110110001000001, 0b110100101110110,
+            ]),
+            (0b00, [
+                0b101010000010010, 0b101000100100101, 0b101111001111100, 0b101101101001011,
+                0b100010111111001, 0b100000011001110, 0b100111110010111, 0b100101010100000,
+            ]),
+            (0b11, [
+                0b011010101011111, 0b011000001101000, 0b011111100110001, 0b011101000000110,
+                0b010010010110100, 0b010000110000011, 0b010111011011010, 0b010101111101101,
+            ]),
+            (0b10, [
+                0b001011010001001, 0b001001110111110, 0b001110011100111, 0b001100111010000,
+                0b000011101100010, 0b000001001010101, 0b000110100001100, 0b000100000111011,
+            ]),
+        ];
+
+        for (level_bits, expected) in table {
+            for (mask, bits) in expected.into_iter().enumerate() {
+                assert_eq!(format_bits(level_bits, mask as u32), bits, "level {:02b}, mask {}", level_bits, mask);
+            }
+        }
+    }
+
+    #[test]
+    fn encodes_short_url_like_reference_encoder() {
+        // Produced by Project Nayuki's qrcodegen 1.8.0 (byte mode, level M, no ECC boost)
+        let expected = [
+            "#######.#..##.###.#######",
+            "#.....#..#.#.##.#.#.....#",
+            "#.###.#.#.....###.#.###.#",
+            "#.###.#...###.#.#.#.###.#",
+            "#.###.#..##..##.#.#.###.#",
+            "#.....#.#.#.....#.#.....#",
+            "#######.#.#.#.#.#.#######",
+            "..........#.#.###........",
+            "#.#...##.#.####.#..#..#.#",
+            "##..#....##.####.###.#.##",
+            "..#.###.###.#..###..###.#",
+            "...###..#..###.....#.#...",
+            ".#...###....##.#.###....#",
+            "..###...#.#.#..##.##...##",
+            "####..#..#..####.....##.#",
+            "..#..#.....##.###.####...",
+            "###.#.##..#..##.#####..#.",
+            "........#.#..##.#...#...#",
+            "#######.##.#....#.#.#...#",
+            "#.....#..##..#..#...#..#.",
+            "#.###.#...####.######..##",
+            "#.###.#...#.#..###..#.##.",
+            "#.###.#.##..###..#.###.##",
+            "#.....#...###.#.#..##....",
+            "#######.##...##.#....#..#",
+        ];
+
+        let qr = QrCode::encode(b"https://ex.co/p/1").unwrap();
+        assert_eq!(qr.size, expected.len());
+        for (y, row) in expected.iter().enumerate() {
+            let actual: String = (0..qr.size).map(|x| if qr.module(x, y) { '#' } else { '.' }).collect();
+            assert_eq!(actual, *row, "row {}", y);
+        }
+    }
+
+    #[test]
+    fn rejects_payloads_beyond_version_10() {
+        assert!(QrCode::encode(&[b'a'; 213]).is_ok());
+        assert!(QrCode::encode(&[b'a'; 214]).is_err());
+    }
+}
@@ -0,0 +1,276 @@
+use crate::storage::Key;
+use crate::types::{
+    EventKind, EventStatus, Partner, PartnerStatus, ReputationBreakdown, ReputationFactor, ReputationStats,
+    SupplyChainEvent,
+};
+use crate::{
+    acts_for_partner, get_event_by_id, get_organisation_by_id, get_user_by_principal, partners_managed_by, EVENT_IDS,
+    LAST_STAGE_UPDATES, PARTNERS, PARTNER_REPUTATION, PRODUCT_HANDLERS, SUPPLY_CHAIN_EVENTS,
+};
+use candid::Principal;
+
+// Factor weights; they add up to the maximum score of 100. Factors without any
+// data yet count as neutral (0.5), while a clean recall record counts in full,
+// so new partners start at 55.
+const RELIABILITY_WEIGHT: u32 = 30;
+const PUNCTUALITY_WEIGHT: u32 = 25;
+const RATINGS_WEIGHT: u32 = 20;
+const CERTIFICATION_WEIGHT: u32 = 15;
+const RECALL_WEIGHT: u32 = 10;
+const NEUTRAL: f64 = 0.5;
+// Each recall traced to the partner removes this share of the recall factor
+const RECALL_PENALTY: f64 = 0.25;
+pub const MIN_RATING: u8 = 1;
+pub const MAX_RATING: u8 = 5;
+
+// Partners an event is attributed to: those the actor acts for as manager or
+// as a member of the manager's organisation. The event's free-text actor name
+// plays no part. Only partners managed by the actor or its fellow members are
+// looked at.
+pub fn partners_for_actor(actor_id: &Principal) -> Vec<Partner> {
+    let mut managers = vec![*actor_id];
+    let organisation = get_user_by_principal(actor_id).ok()
+        .and_then(|user| user.organisation_id)
+        .and_then(|organisation_id| get_organisation_by_id(&organisation_id).ok());
+    if let Some(organisation) = organisation {
+        managers.extend(organisation.members.iter().map(|member| member.user_id).filter(|member| member != actor_id));
+    }
+
+    managers.iter()
+        .flat_map(partners_managed_by)
+        .filter(|partner| partner.status != PartnerStatus::Removed)
+        .filter(|partner| acts_for_partner(partner, actor_id))
+        .collect()
+}
+
+// Updates the partners affected by an event that has just been appended to its
+// product's history. Stage updates count towards the actor's partners and settle
+// the delivery estimate of the previous stage update, and amendments revise
+// both; recalls count against every partner credited with a stage update of the
+// product. Only the product's latest stage update and its handlers are looked
+// up, never its whole history.
+pub fn record_event(event: &SupplyChainEvent) {
+    match &event.kind {
+        None => {
+            for partner in partners_for_actor(&event.actor_id) {
+                PRODUCT_HANDLERS.with(|handlers| {
+                    handlers.borrow_mut().insert(Key(event.product_id.clone(), partner.id.clone()), ());
+                });
+                update_stats(partner, |stats| {
+                    stats.events_recorded += 1;
+                    count_outcome(stats, event, 1);
+                });
+            }
+
+            // The previous stage update as last revised
+            let previous = LAST_STAGE_UPDATES.with(|updates| {
+                updates.borrow_mut().insert(event.product_id.clone(), event.id.clone())
+            });
+            if let Some(previous) = previous.and_then(|previous_id| get_event_by_id(&previous_id).ok()) {
+                if let Some(estimated_arrival) = previous.estimated_arrival {
+                    for partner in partners_for_actor(&original_stage_update(previous).actor_id) {
+                        update_stats(partner, |stats| settle_delivery(stats, event.timestamp, estimated_arrival, 1));
+                    }
+                }
+            }
+        }
+        Some(EventKind::Amendment { amends, .. }) => record_amendment(event, amends),
+        Some(EventKind::Recall { .. }) => {
+            for partner in handlers(&event.product_id) {
+                update_stats(partner, |stats| stats.recalls_traced += 1);
+            }
+        }
+        // Custody handoffs do not describe new work
+        Some(_) => {}
+    }
+}
+
+// An amendment replaces the outcome of the revision it corrects. The work stays
+// credited to the actor of the original stage update, and a delivery estimate
+// that has already been settled by the next stage update is settled again.
+fn record_amendment(amendment: &SupplyChainEvent, amends: &str) {
+    let Ok(revised) = get_event_by_id(amends) else {
+        return;
+    };
+
+    LAST_STAGE_UPDATES.with(|updates| {
+        let mut updates = updates.borrow_mut();
+        if updates.get(&amendment.product_id).as_deref() == Some(amends) {
+            updates.insert(amendment.product_id.clone(), amendment.id.clone());
+        }
+    });
+
+    let original = original_stage_update(revised.clone());
+    let next_arrival = (revised.estimated_arrival != amendment.estimated_arrival)
+        .then(|| next_stage_update(&original, amendment))
+        .flatten()
+        .map(|next| next.timestamp);
+
+    for partner in partners_for_actor(&original.actor_id) {
+        update_stats(partner, |stats| {
+            count_outcome(stats, &revised, -1);
+            count_outcome(stats, amendment, 1);
+            if let Some(arrival) = next_arrival {
+                if let Some(estimated_arrival) = revised.estimated_arrival {
+                    settle_delivery(stats, arrival, estimated_arrival, -1);
+                }
+                if let Some(estimated_arrival) = amendment.estimated_arrival {
+                    settle_delivery(stats, arrival, estimated_arrival, 1);
+                }
+            }
+        });
+    }
+}
+
+// Adds (`sign` 1) or takes back (`sign` -1) the outcome of a stage update revision
+fn count_outcome(stats: &mut ReputationStats, event: &SupplyChainEvent, sign: i64) {
+    match event.status {
+        EventStatus::Completed => adjust(&mut stats.completed_events, sign),
+        EventStatus::Failed => adjust(&mut stats.failed_events, sign),
+        EventStatus::Pending | EventStatus::InProgress => {}
+    }
+    if !event.certifications.is_empty() {
+        adjust(&mut stats.certified_events, sign);
+    }
+}
+
+fn settle_delivery(stats: &mut ReputationStats, arrival: u64, estimated_arrival: u64, sign: i64) {
+    adjust(&mut stats.deliveries_with_eta, sign);
+    if arrival <= estimated_arrival {
+        adjust(&mut stats.on_time_deliveries, sign);
+    }
+}
+
+// Partners may have gained or lost members since the counts were made, so
+// taking one back never goes below zero
+fn adjust(count: &mut u64, sign: i64) {
+    *count = count.saturating_add_signed(sign);
+}
+
+// Follows amendments back to the stage update they correct
+fn original_stage_update(mut event: SupplyChainEvent) -> SupplyChainEvent {
+    while let Some(EventKind::Amendment { amends, .. }) = &event.kind {
+        match get_event_by_id(amends) {
+            Ok(amended) => event = amended,
+            Err(_) => break,
+        }
+    }
+    event
+}
+
+// The stage update after `original` that had been recorded before `amendment`,
+// which is the one that settled the original's delivery estimate
+fn next_stage_update(original: &SupplyChainEvent, amendment: &SupplyChainEvent) -> Option<SupplyChainEvent> {
+    let Key(product_id, from) = EVENT_IDS.with(|event_ids| event_ids.borrow().get(&original.id))?;
+    let Key(_, to) = EVENT_IDS.with(|event_ids| event_ids.borrow().get(&amendment.id))?;
+
+    SUPPLY_CHAIN_EVENTS.with(|events| {
+        events.borrow()
+            .range(Key(product_id.clone(), from + 1)..Key(product_id, to))
+            .map(|(_, event)| event)
+            .find(|event| event.kind.is_none())
+    })
+}
+
+// Whether the partner was credited with a stage update of the product
+pub fn handled_product(product_id: &str, partner_id: &str) -> bool {
+    PRODUCT_HANDLERS.with(|handlers| handlers.borrow().contains_key(&Key(product_id.to_string(), partner_id.to_string())))
+}
+
+fn handlers(product_id: &str) -> Vec<Partner> {
+    let partner_ids: Vec<String> = PRODUCT_HANDLERS.with(|handlers| {
+        handlers.borrow()
+            .range(Key(product_id.to_string(), String::new())..)
+            .take_while(|(Key(handled_product_id, _), _)| handled_product_id == product_id)
+            .map(|(Key(_, partner_id), _)| partner_id)
+            .collect()
+    });
+    partner_ids.iter()
+        .filter_map(|partner_id| PARTNERS.with(|partners| partners.borrow().get(partner_id)))
+        .filter(|partner| partner.status != PartnerStatus::Removed)
+        .collect()
+}
+
+// Replaces `previous` (if any) with `rating` from one counterparty
+pub fn record_rating(partner: Partner, previous: Option<u8>, rating: u8) {
+    update_stats(partner, |stats| {
+        if let Some(previous) = previous {
+            stats.ratings_total -= previous as u64;
+        } else {
+            stats.ratings_count += 1;
+        }
+        stats.ratings_total += rating as u64;
+    });
+}
+
+pub fn stats(partner_id: &str) -> ReputationStats {
+    PARTNER_REPUTATION.with(|reputation| reputation.borrow().get(&partner_id.to_string()))
+        .unwrap_or_default()
+}
+
+pub fn breakdown(partner_id: &str) -> ReputationBreakdown {
+    let stats = stats(partner_id);
+    let factors = factors(&stats);
+    ReputationBreakdown {
+        partner_id: partner_id.to_string(),
+        score: score(&factors),
+        factors,
+        stats,
+    }
+}
+
+fn update_stats(mut partner: Partner, update: impl FnOnce(&mut ReputationStats)) {
+    let mut stats = stats(&partner.id);
+    update(&mut stats);
+
+    partner.reputation_score = score(&factors(&stats));
+    PARTNER_REPUTATION.with(|reputation| reputation.borrow_mut().insert(partner.id.clone(), stats));
+    PARTNERS.with(|partners| partners.borrow_mut().insert(partner.id.clone(), partner));
+}
+
+fn factors(stats: &ReputationStats) -> Vec<ReputationFactor> {
+    let settled_events = stats.completed_events + stats.failed_events;
+    let average_rating = (stats.ratings_count > 0).then(|| {
+        let average = stats.ratings_total as f64 / stats.ratings_count as f64;
+        (average - MIN_RATING as f64) / (MAX_RATING - MIN_RATING) as f64
+    });
+    let recall_record = (1.0 - RECALL_PENALTY * stats.recalls_traced as f64).max(0.0);
+
+    vec![
+        factor("reliability", RELIABILITY_WEIGHT, ratio(stats.completed_events, settled_events), settled_events),
+        factor(
+            "punctuality",
+            PUNCTUALITY_WEIGHT,
+            ratio(stats.on_time_deliveries, stats.deliveries_with_eta),
+            stats.deliveries_with_eta,
+        ),
+        factor("ratings", RATINGS_WEIGHT, average_rating, stats.ratings_count),
+        factor(
+            "certification",
+            CERTIFICATION_WEIGHT,
+            ratio(stats.certified_events, stats.events_recorded),
+            stats.events_recorded,
+        ),
+        factor("recalls", RECALL_WEIGHT, Some(recall_record), stats.recalls_traced),
+    ]
+}
+
+fn factor(name: &str, weight: u32, value: Option<f64>, sample_size: u64) -> ReputationFactor {
+    let value = value.unwrap_or(NEUTRAL);
+    ReputationFactor {
+        name: name.to_string(),
+        weight,
+        value,
+        contribution: value * weight as f64,
+        sample_size,
+    }
+}
+
+fn ratio(part: u64, total: u64) -> Option<f64> {
+    (total > 0).then(|| part as f64 / total as f64)
+}
+
+fn score(factors: &[ReputationFactor]) -> u32 {
+    let total: f64 = factors.iter().map(|factor| factor.contribution).sum();
+    total.round().clamp(0.0, 100.0) as u32
+}
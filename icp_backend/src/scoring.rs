@@ -0,0 +1,130 @@
+use crate::certifications::certification_label;
+use crate::types::{
+    CertificationState, EthicalScoreBreakdown, EventKind, EventStatus, Product, ScoreContribution, ScoringPolicy,
+    SupplyChainEvent,
+};
+
+pub const MAX_SCORE: f64 = 100.0;
+
+// Scores a product under `policy`. `events` must have superseded_by filled in so
+// that only the current revision of amended events counts.
+pub fn score_breakdown(product: &Product, events: &[SupplyChainEvent], policy: &ScoringPolicy) -> EthicalScoreBreakdown {
+    let mut contributions = Vec::new();
+
+    match product.sustainability_score {
+        Some(score) => contributions.push(contribution("sustainability_score", "Declared sustainability score", score)),
+        None => contributions.push(contribution("base_score", "No sustainability score declared", policy.base_score)),
+    }
+
+    for certification in &product.certifications {
+        let standard = certification_label(certification);
+        let lapsed = product.certification_alerts.iter().flatten().find(|alert| {
+            alert.certification_id == *certification && alert.state != CertificationState::Expiring
+        });
+        // Lapsed certifications stay listed but earn the policy's lapse points, if it sets any
+        if let (Some(alert), Some(points)) = (lapsed, policy.lapsed_certification_points) {
+            let lapse = match alert.state {
+                CertificationState::Revoked => "was revoked",
+                _ => "has expired",
+            };
+            contributions.push(contribution(
+                &format!("certification:{}", certification),
+                &format!("Product certification {} {}", standard, lapse),
+                points,
+            ));
+            continue;
+        }
+
+        let points = policy.certification_weights.iter()
+            .find(|weight| weight.certification.eq_ignore_ascii_case(&standard))
+            .map_or(policy.default_certification_points, |weight| weight.points);
+        contributions.push(contribution(
+            &format!("certification:{}", certification),
+            &format!("Product certification {}", standard),
+            points,
+        ));
+    }
+
+    for risk in &policy.raw_material_risks {
+        let risk_material = risk.material.to_lowercase();
+        if let Some(material) = product.raw_materials.iter().find(|material| material.to_lowercase().contains(&risk_material)) {
+            contributions.push(contribution(
+                &format!("raw_material:{}", risk.material),
+                &format!("Raw material {} is on the risk list", material),
+                -risk.penalty,
+            ));
+        }
+    }
+
+    for event in events.iter().filter(|event| event.superseded_by.is_none()) {
+        match &event.kind {
+            Some(EventKind::Recall { recall_id }) => {
+                if policy.recall_penalty > 0.0 {
+                    contributions.push(contribution(
+                        &format!("recall:{}", recall_id),
+                        &format!("Recalled under {}", recall_id),
+                        -policy.recall_penalty,
+                    ));
+                }
+            }
+            None | Some(EventKind::Amendment { .. }) => {
+                if event.status == EventStatus::Failed && policy.failed_event_penalty > 0.0 {
+                    contributions.push(contribution(
+                        &format!("event:{}", event.id),
+                        &format!("{:?} failed", event.stage),
+                        -policy.failed_event_penalty,
+                    ));
+                }
+                if !event.certifications.is_empty() {
+                    contributions.push(contribution(
+                        &format!("event:{}", event.id),
+                        &format!("{:?} completed with certifications", event.stage),
+                        policy.certified_event_points,
+                    ));
+                }
+            }
+            Some(_) => {}
+        }
+    }
+
+    let uncapped_score: f64 = contributions.iter().map(|contribution| contribution.points).sum();
+
+    EthicalScoreBreakdown {
+        product_id: product.id.clone(),
+        policy_version: policy.version,
+        score: uncapped_score.clamp(0.0, MAX_SCORE),
+        uncapped_score,
+        contributions,
+    }
+}
+
+pub fn validate_policy(policy: &ScoringPolicy) -> Result<(), String> {
+    if !(0.0..=MAX_SCORE).contains(&policy.base_score) {
+        return Err(format!("Base score must be between 0 and {}", MAX_SCORE));
+    }
+
+    let penalties = [policy.failed_event_penalty, policy.recall_penalty]
+        .into_iter()
+        .chain(policy.raw_material_risks.iter().map(|risk| risk.penalty));
+    if penalties.into_iter().any(|penalty| !penalty.is_finite() || penalty < 0.0) {
+        return Err("Penalties must be non-negative".to_string());
+    }
+
+    let points = [policy.default_certification_points, policy.certified_event_points]
+        .into_iter()
+        .chain(policy.lapsed_certification_points)
+        .chain(policy.certification_weights.iter().map(|weight| weight.points));
+    if points.into_iter().any(|points| !points.is_finite()) {
+        return Err("Weights must be finite numbers".to_string());
+    }
+
+    Ok(())
+}
+
+fn contribution(source: &str, description: &str, points: f64) -> ScoreContribution {
+    ScoreContribution {
+        source: source.to_string(),
+        description: description.to_string(),
+        points,
+    }
+}
@@ -1,4 +1,105 @@
 use crate::types::*;
+use candid::{CandidType, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+
+// Every record in stable memory is written as `RECORD_MAGIC`, a one-byte schema
+// version and the Candid payload. Records written before the envelope existed are
+// bare Candid (starting with `DIDL`) and are read as version 0.
+const RECORD_MAGIC: &[u8; 3] = b"SCR";
+
+/// A record kept in stable memory under a versioned envelope.
+pub trait VersionedRecord: CandidType + DeserializeOwned {
+    /// Version written by the current code.
+    const SCHEMA_VERSION: u8;
+
+    /// Decodes a payload stored under `version`. Types override this to
+    /// upgrade payloads written by older schema versions.
+    fn migrate(version: u8, payload: &[u8]) -> Self {
+        if version != Self::SCHEMA_VERSION {
+            unsupported_version::<Self>(version);
+        }
+        decode_payload(payload)
+    }
+}
+
+/// Implements `Storable` for a `VersionedRecord` using the versioned envelope.
+/// Values are unbounded so records can grow between schema versions.
+macro_rules! impl_versioned_storable {
+    ($($ty:ty),+ $(,)?) => {
+        $(
+            impl ic_stable_structures::Storable for $ty {
+                fn to_bytes(&self) -> std::borrow::Cow<'_, [u8]> {
+                    std::borrow::Cow::Owned($crate::storage::encode_record(self))
+                }
+
+                fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                    let (version, payload) = $crate::storage::split_record(&bytes);
+                    <$ty as $crate::storage::VersionedRecord>::migrate(version, payload)
+                }
+
+                const BOUND: ic_stable_structures::storable::Bound =
+                    ic_stable_structures::storable::Bound::Unbounded;
+            }
+        )+
+    };
+}
+pub(crate) use impl_versioned_storable;
+
+// Two-part key for the maps indexed by (ID, sequence), (index key, ID) and the
+// like. ic-stable-structures only stores tuples whose parts are both bounded, so
+// the first part is written with its length ahead of the second. Keys order like
+// the tuple they replace.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Key<A, B>(pub A, pub B);
+
+impl<A: Storable, B: Storable> Storable for Key<A, B> {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let first = self.0.to_bytes();
+        let mut bytes = (first.len() as u32).to_be_bytes().to_vec();
+        bytes.extend_from_slice(&first);
+        bytes.extend_from_slice(&self.1.to_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (length, rest) = bytes.split_at(4);
+        let length = u32::from_be_bytes(length.try_into().expect("Key length is four bytes")) as usize;
+        let (first, second) = rest.split_at(length);
+        Key(A::from_bytes(Cow::Borrowed(first)), B::from_bytes(Cow::Borrowed(second)))
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+pub fn encode_record<T: VersionedRecord>(value: &T) -> Vec<u8> {
+    let mut bytes = RECORD_MAGIC.to_vec();
+    bytes.push(T::SCHEMA_VERSION);
+    bytes.extend(candid::encode_one(value).expect("Failed to encode stored record"));
+    bytes
+}
+
+pub fn split_record(bytes: &[u8]) -> (u8, &[u8]) {
+    match bytes.strip_prefix(RECORD_MAGIC.as_slice()) {
+        Some([version, payload @ ..]) => (*version, payload),
+        _ => (0, bytes),
+    }
+}
+
+pub fn decode_payload<T: CandidType + DeserializeOwned>(payload: &[u8]) -> T {
+    candid::decode_one(payload)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Failed to decode stored record: {}", e)))
+}
+
+pub fn unsupported_version<T>(version: u8) -> ! {
+    ic_cdk::trap(&format!(
+        "Unsupported schema version {} for {}",
+        version,
+        std::any::type_name::<T>()
+    ))
+}
 
 pub fn get_default_permissions(role: &UserRole) -> UserPermissions {
     match role {
@@ -53,3 +154,106 @@ pub fn get_default_permissions(role: &UserRole) -> UserPermissions {
         },
     }
 }
+
+// Version 1 reproduces the original fixed formula: the sustainability score (or
+// 50), +5 per product certification and +2 per certified stage update
+pub fn default_scoring_policy() -> ScoringPolicy {
+    ScoringPolicy {
+        version: 1,
+        base_score: 50.0,
+        certification_weights: Vec::new(),
+        default_certification_points: 5.0,
+        certified_event_points: 2.0,
+        failed_event_penalty: 0.0,
+        recall_penalty: 0.0,
+        raw_material_risks: Vec::new(),
+        lapsed_certification_points: None,
+        created_by: Principal::anonymous(),
+        created_at: 0,
+    }
+}
+
+pub fn default_transition_rules() -> StageTransitionRules {
+    use EventStatus::*;
+    use SupplyChainStage::*;
+
+    let rule = |from_stage, from_status, to_stages| StageTransitionRule {
+        from_stage,
+        from_status,
+        to_stages,
+    };
+
+    let mut rules = Vec::new();
+    for stage in [
+        RawMaterialSourcing,
+        Manufacturing,
+        QualityControl,
+        Packaging,
+        Shipping,
+        Distribution,
+        Retail,
+    ] {
+        // Unfinished or failed work can only be continued or retried at the same stage
+        rules.push(rule(stage.clone(), Pending, vec![stage.clone()]));
+        rules.push(rule(stage.clone(), InProgress, vec![stage.clone()]));
+        rules.push(rule(stage.clone(), Failed, vec![stage]));
+    }
+
+    // Failed quality control sends the product back for rework
+    rules.push(rule(QualityControl, Failed, vec![Manufacturing]));
+
+    rules.push(rule(RawMaterialSourcing, Completed, vec![Manufacturing]));
+    rules.push(rule(Manufacturing, Completed, vec![QualityControl, Packaging]));
+    rules.push(rule(QualityControl, Completed, vec![Packaging]));
+    rules.push(rule(Packaging, Completed, vec![Shipping]));
+    rules.push(rule(Shipping, Completed, vec![Shipping, Distribution, Retail]));
+    rules.push(rule(Distribution, Completed, vec![Shipping, Retail]));
+
+    // Arriving at the next stop closes an open shipment or distribution leg
+    rules.push(rule(Shipping, InProgress, vec![Distribution, Retail]));
+    rules.push(rule(Distribution, InProgress, vec![Shipping, Retail]));
+
+    StageTransitionRules(rules)
+}
+
+// Every stage reachable from the latest (stage, status) pair, in rule order
+pub fn next_stages(
+    rules: &StageTransitionRules,
+    from_stage: &SupplyChainStage,
+    from_status: &EventStatus,
+) -> Vec<SupplyChainStage> {
+    let mut stages: Vec<SupplyChainStage> = Vec::new();
+    for rule in rules.0.iter().filter(|rule| rule.from_stage == *from_stage && rule.from_status == *from_status) {
+        for stage in &rule.to_stages {
+            if !stages.contains(stage) {
+                stages.push(stage.clone());
+            }
+        }
+    }
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_round_trips_with_unbounded_parts() {
+        let key = Key("CT-2024-00A1B2".to_string(), 7u64);
+        assert_eq!(Key::<String, u64>::from_bytes(key.to_bytes()), key);
+
+        let key = Key("organic cotton".to_string(), "CT-2024-00A1B2".to_string());
+        assert_eq!(Key::<String, String>::from_bytes(key.to_bytes()), key);
+    }
+
+    #[test]
+    fn keys_order_by_first_part_then_second() {
+        let mut map = ic_stable_structures::StableBTreeMap::init(ic_stable_structures::DefaultMemoryImpl::default());
+        for (product_id, sequence) in [("CT-B", 0u64), ("CT-A", 10), ("CT-A", 2)] {
+            map.insert(Key(product_id.to_string(), sequence), ());
+        }
+
+        let keys: Vec<(String, u64)> = map.iter().map(|(Key(product_id, sequence), _)| (product_id, sequence)).collect();
+        assert_eq!(keys, vec![("CT-A".to_string(), 2), ("CT-A".to_string(), 10), ("CT-B".to_string(), 0)]);
+    }
+}
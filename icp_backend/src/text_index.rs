@@ -0,0 +1,170 @@
+use crate::access::AccessLevel;
+use crate::certifications::certification_label;
+use crate::types::{Product, SupplyChainEvent};
+use crate::storage::Key;
+use crate::{Memory, RESTRICTED_TEXT_INDEX, TEXT_INDEX};
+use ic_stable_structures::StableBTreeMap;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::thread::LocalKey;
+
+// Inverted index for free-text search, keyed by (token, product_id). The value
+// is the product's accumulated weight for the token across its own fields and
+// every event in its history, so ranking needs no further lookups. Event
+// metadata, which redacted views hide, is weighted in RESTRICTED_TEXT_INDEX.
+const NAME_WEIGHT: u32 = 5;
+const CERTIFICATION_WEIGHT: u32 = 3;
+const RAW_MATERIAL_WEIGHT: u32 = 3;
+const DESCRIPTION_WEIGHT: u32 = 2;
+const EVENT_WEIGHT: u32 = 1;
+
+const MIN_TOKEN_LENGTH: usize = 2;
+const MAX_TOKEN_LENGTH: usize = 32;
+// Query terms that match a token exactly score higher than prefix matches
+const EXACT_MATCH_BONUS: u32 = 2;
+
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|token| token.chars().count() >= MIN_TOKEN_LENGTH)
+        .map(|token| token.to_lowercase().chars().take(MAX_TOKEN_LENGTH).collect())
+        .collect()
+}
+
+fn product_terms(product: &Product) -> BTreeMap<String, u32> {
+    let mut terms = BTreeMap::new();
+    add_terms(&mut terms, &product.name, NAME_WEIGHT);
+    if let Some(description) = &product.description {
+        add_terms(&mut terms, description, DESCRIPTION_WEIGHT);
+    }
+    for material in &product.raw_materials {
+        add_terms(&mut terms, material, RAW_MATERIAL_WEIGHT);
+    }
+    for certification in &product.certifications {
+        add_terms(&mut terms, &certification_label(certification), CERTIFICATION_WEIGHT);
+    }
+    terms
+}
+
+fn event_terms(event: &SupplyChainEvent) -> BTreeMap<String, u32> {
+    let mut terms = BTreeMap::new();
+    add_terms(&mut terms, &event.details, EVENT_WEIGHT);
+    terms
+}
+
+fn restricted_event_terms(event: &SupplyChainEvent) -> BTreeMap<String, u32> {
+    let mut terms = BTreeMap::new();
+    for value in event.metadata.values() {
+        add_terms(&mut terms, value, EVENT_WEIGHT);
+    }
+    terms
+}
+
+fn add_terms(terms: &mut BTreeMap<String, u32>, text: &str, weight: u32) {
+    for token in tokenize(text) {
+        *terms.entry(token).or_insert(0) += weight;
+    }
+}
+
+// Replaces the contribution of the product's own fields, leaving the weights
+// contributed by its events in place
+pub fn reindex_product(previous: Option<&Product>, product: &Product) {
+    let old_terms = previous.map(product_terms).unwrap_or_default();
+    let new_terms = product_terms(product);
+    if old_terms == new_terms {
+        return;
+    }
+
+    for (token, weight) in old_terms {
+        adjust_weight(&TEXT_INDEX, &token, &product.id, -(weight as i64));
+    }
+    for (token, weight) in new_terms {
+        adjust_weight(&TEXT_INDEX, &token, &product.id, weight as i64);
+    }
+}
+
+pub fn index_event(event: &SupplyChainEvent) {
+    for (token, weight) in event_terms(event) {
+        adjust_weight(&TEXT_INDEX, &token, &event.product_id, weight as i64);
+    }
+    for (token, weight) in restricted_event_terms(event) {
+        adjust_weight(&RESTRICTED_TEXT_INDEX, &token, &event.product_id, weight as i64);
+    }
+}
+
+type TokenIndex = RefCell<StableBTreeMap<Key<String, String>, u32, Memory>>;
+
+fn adjust_weight(index: &'static LocalKey<TokenIndex>, token: &str, product_id: &str, delta: i64) {
+    index.with(|index| {
+        let mut index = index.borrow_mut();
+        let key = Key(token.to_string(), product_id.to_string());
+        let weight = index.get(&key).unwrap_or(0) as i64 + delta;
+        if weight > 0 {
+            index.insert(key, weight as u32);
+        } else {
+            index.remove(&key);
+        }
+    });
+}
+
+// Scores products that match every query term, where a term matches any token
+// it is a prefix of. `access_level` decides per product whether it is skipped
+// and whether restricted tokens count. Returns (product_id, score) pairs, best first.
+pub fn search(query: &str, access_level: impl Fn(&str) -> AccessLevel) -> Vec<(String, u32)> {
+    let terms = tokenize(query);
+    if terms.is_empty() {
+        return Vec::new();
+    }
+
+    let mut levels: BTreeMap<String, AccessLevel> = BTreeMap::new();
+    let mut level_of = |product_id: &str| {
+        *levels.entry(product_id.to_string()).or_insert_with(|| access_level(product_id))
+    };
+
+    let mut scores: Option<BTreeMap<String, u32>> = None;
+    for term in terms {
+        let term_scores = score_term(&term, &mut level_of);
+        scores = Some(match scores {
+            None => term_scores,
+            Some(scores) => scores
+                .into_iter()
+                .filter_map(|(product_id, score)| {
+                    term_scores.get(&product_id).map(|term_score| (product_id, score + term_score))
+                })
+                .collect(),
+        });
+    }
+
+    let mut ranked: Vec<(String, u32)> = scores.unwrap_or_default().into_iter().collect();
+    ranked.sort_by(|(a_id, a_score), (b_id, b_score)| b_score.cmp(a_score).then_with(|| a_id.cmp(b_id)));
+    ranked
+}
+
+// Best weight per visible product among the tokens that start with `term`
+fn score_term(term: &str, level_of: &mut impl FnMut(&str) -> AccessLevel) -> BTreeMap<String, u32> {
+    let mut weights = matching_weights(&TEXT_INDEX, term);
+    for (key, weight) in matching_weights(&RESTRICTED_TEXT_INDEX, term) {
+        if level_of(&key.1) == AccessLevel::Full {
+            *weights.entry(key).or_insert(0) += weight;
+        }
+    }
+
+    let mut scores: BTreeMap<String, u32> = BTreeMap::new();
+    for (Key(token, product_id), weight) in weights {
+        if level_of(&product_id) == AccessLevel::Hidden {
+            continue;
+        }
+        let score = if token == term { weight * EXACT_MATCH_BONUS } else { weight };
+        let best = scores.entry(product_id).or_insert(0);
+        *best = (*best).max(score);
+    }
+    scores
+}
+
+fn matching_weights(index: &'static LocalKey<TokenIndex>, term: &str) -> BTreeMap<Key<String, String>, u32> {
+    index.with(|index| {
+        index.borrow()
+            .range(Key(term.to_string(), String::new())..)
+            .take_while(|(Key(token, _), _)| token.starts_with(term))
+            .collect()
+    })
+}
@@ -1,7 +1,6 @@
+use crate::storage::{decode_payload, impl_versioned_storable, unsupported_version, VersionedRecord};
 use candid::{CandidType, Deserialize, Principal};
-use ic_stable_structures::Storable;
 use serde::Serialize;
-use std::borrow::Cow;
 use std::collections::HashMap;
 
 // User Types
@@ -16,6 +15,9 @@ pub struct User {
     pub created_at: u64,
     pub is_verified: bool,
     pub permissions: UserPermissions,
+    pub pending_role: Option<UserRole>,
+    // Organisation the user acts for; a user belongs to at most one
+    pub organisation_id: Option<String>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -24,9 +26,15 @@ pub struct UserRegistration {
     pub first_name: String,
     pub last_name: String,
     pub company: String,
+    // Requested role; new users start as Consumer until an admin approves it
     pub role: UserRole,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct InitArgs {
+    pub admins: Vec<Principal>,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Copy)]
 pub enum UserRole {
     Manufacturer,
@@ -47,6 +55,30 @@ pub struct UserPermissions {
     pub can_verify_users: bool,
 }
 
+// Organisation Types
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Organisation {
+    pub id: String,
+    pub name: String,
+    pub members: Vec<OrganisationMember>,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct OrganisationMember {
+    pub user_id: Principal,
+    pub role: OrganisationRole,
+    pub joined_at: u64,
+}
+
+// Owners and admins manage membership; only owners can appoint owners
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum OrganisationRole {
+    Owner,
+    Admin,
+    Member,
+}
+
 // Product Types
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct Product {
@@ -66,6 +98,25 @@ pub struct Product {
     pub current_location: String,
     pub created_at: u64,
     pub updated_at: u64,
+    // Principal currently holding the goods; None for products registered before
+    // custody tracking, whose custodian is the manufacturer
+    pub custodian: Option<Principal>,
+    pub pending_handoff: Option<CustodyHandoff>,
+    // Owning organisation. Its members act as the manufacturer; None for products
+    // of manufacturers without an organisation, which belong to `manufacturer_id`.
+    pub organisation_id: Option<String>,
+    // Referenced certifications that are about to lapse or no longer hold, as
+    // flagged by the periodic certification scan
+    pub certification_alerts: Option<Vec<CertificationAlert>>,
+}
+
+// A transfer offered by the current custodian, waiting for the recipient to accept
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CustodyHandoff {
+    pub from: Principal,
+    pub to: Principal,
+    pub initiated_at: u64,
+    pub event_id: String,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -82,6 +133,40 @@ pub struct ProductRegistration {
     pub estimated_value: Option<f64>,
 }
 
+// Field-level patch for update_product; None leaves a field unchanged
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProductUpdate {
+    pub name: Option<String>,
+    pub category: Option<String>,
+    // Optional product fields take Some(None) to clear the value
+    pub description: Option<Option<String>>,
+    pub batch_number: Option<Option<String>>,
+    pub production_date: Option<u64>,
+    pub raw_materials: Option<Vec<String>>,
+    pub certifications: Option<Vec<String>>,
+    pub sustainability_score: Option<Option<f64>>,
+    pub estimated_value: Option<Option<f64>>,
+    pub reason: Option<String>,
+}
+
+// Old and new values are rendered with their Debug representation
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FieldChange {
+    pub field: String,
+    pub old_value: String,
+    pub new_value: String,
+}
+
+// One change log entry per successful update_product call
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProductChange {
+    pub product_id: String,
+    pub changed_by: Principal,
+    pub changed_at: u64,
+    pub changes: Vec<FieldChange>,
+    pub reason: Option<String>,
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum ProductStatus {
     Manufacturing,
@@ -95,6 +180,7 @@ pub struct ProductWithHistory {
     pub product: Product,
     pub supply_chain_events: Vec<SupplyChainEvent>,
     pub ethical_score: f64,
+    pub head_hash: Option<String>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -102,8 +188,115 @@ pub struct ProductSearchQuery {
     pub name: Option<String>,
     pub category: Option<String>,
     pub manufacturer: Option<String>,
+    pub manufacturer_id: Option<Principal>,
+    pub organisation_id: Option<String>,
     pub status: Option<ProductStatus>,
+    pub batch_number: Option<String>,
+    pub production_date_from: Option<u64>,
+    pub production_date_to: Option<u64>,
+    pub updated_from: Option<u64>,
+    pub updated_to: Option<u64>,
+    // Products must hold every listed certification (case-insensitive)
+    pub certifications: Option<Vec<String>>,
+    // Products must contain every listed raw material (case-insensitive substring)
+    pub raw_materials: Option<Vec<String>>,
+    pub location: Option<String>,
+    pub sort_by: Option<ProductSortKey>,
+    pub sort_order: Option<SortOrder>,
     pub limit: Option<u32>,
+    pub cursor: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TextSearchHit {
+    pub product: Product,
+    pub score: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProductSortKey {
+    CreatedAt,
+    UpdatedAt,
+    ProductionDate,
+    EthicalScore,
+    EstimatedValue,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+}
+
+// Product Access Types
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProductVisibility {
+    // Anyone can read the product; outsiders get the redacted view
+    Public,
+    // Only registered partners and stakeholders can read the product
+    PartnersOnly,
+    // Only stakeholders and grantees can read the product
+    Private,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AccessGrant {
+    Principal(Principal),
+    // Every member of the partner organisation with this partner ID
+    Partner(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProductAccess {
+    pub visibility: ProductVisibility,
+    pub grants: Vec<AccessGrant>,
+}
+
+// Schema version 1, when partners were identified by their registering principal
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum AccessGrantV1 {
+    Principal(Principal),
+    Partner(Principal),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProductAccessV1 {
+    pub visibility: ProductVisibility,
+    pub grants: Vec<AccessGrantV1>,
+}
+
+// Partner grants fall back to the principal that registered the partner; they
+// can be re-granted by partner ID
+impl From<ProductAccessV1> for ProductAccess {
+    fn from(access: ProductAccessV1) -> Self {
+        ProductAccess {
+            visibility: access.visibility,
+            grants: access.grants
+                .into_iter()
+                .map(|grant| match grant {
+                    AccessGrantV1::Principal(principal) | AccessGrantV1::Partner(principal) => {
+                        AccessGrant::Principal(principal)
+                    }
+                })
+                .collect(),
+        }
+    }
+}
+
+// Pagination Types
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PageRequest {
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+// `next_cursor` is None on the last page; `total_count_hint` is an upper bound
+// when the exact count would require loading every record
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+    pub total_count_hint: Option<u64>,
 }
 
 // Supply Chain Types
@@ -121,21 +314,99 @@ pub struct SupplyChainEvent {
     pub certifications: Vec<String>,
     pub estimated_arrival: Option<u64>,
     pub metadata: HashMap<String, String>,
+    // None for regular stage updates recorded through add_supply_chain_event
+    pub kind: Option<EventKind>,
+    // Lot the update was recorded for; set on every product's copy of a lot event
+    pub lot_id: Option<String>,
+    // Hex SHA-256 of the previous event in the product history (GENESIS_HASH for the first)
+    pub previous_hash: String,
+    // Hex SHA-256 over this event's canonical encoding, including previous_hash
+    pub hash: String,
+    // ID of the amendment that corrects this event. Filled in by queries from the
+    // amendment index; never stored or hashed.
+    pub superseded_by: Option<String>,
 }
 
+// Event layout before the hash chain was added (schema versions 0 and 1)
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct SupplyChainEventInput {
+pub struct SupplyChainEventV1 {
+    pub id: String,
     pub product_id: String,
     pub stage: SupplyChainStage,
     pub location: String,
+    pub timestamp: u64,
+    pub actor: String,
+    pub actor_id: Principal,
+    pub status: EventStatus,
+    pub details: String,
+    pub certifications: Vec<String>,
+    pub estimated_arrival: Option<u64>,
+    pub metadata: HashMap<String, String>,
+    pub kind: Option<EventKind>,
+}
+
+// Hashes are left empty here; the storage migration rebuilds each product's chain
+impl From<SupplyChainEventV1> for SupplyChainEvent {
+    fn from(event: SupplyChainEventV1) -> Self {
+        SupplyChainEvent {
+            id: event.id,
+            product_id: event.product_id,
+            stage: event.stage,
+            location: event.location,
+            timestamp: event.timestamp,
+            actor: event.actor,
+            actor_id: event.actor_id,
+            status: event.status,
+            details: event.details,
+            certifications: event.certifications,
+            estimated_arrival: event.estimated_arrival,
+            metadata: event.metadata,
+            kind: event.kind,
+            lot_id: None,
+            previous_hash: String::new(),
+            hash: String::new(),
+            superseded_by: None,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum EventKind {
+    Recall { recall_id: String },
+    // Corrects the event `amends`, which stays in the history as superseded
+    Amendment { amends: String, reason: String },
+    // Signed by the custodian handing the goods over
+    HandoffInitiated { to: Principal },
+    // Signed by the recipient taking custody
+    HandoffAccepted { from: Principal },
+}
+
+// Corrected values for an event; the stage of the original is kept
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SupplyChainEventAmendment {
+    pub event_id: String,
+    pub location: String,
     pub status: EventStatus,
     pub details: String,
     pub certifications: Vec<String>,
     pub estimated_arrival: Option<u64>,
     pub metadata: HashMap<String, String>,
+    pub reason: String,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SupplyChainEventInput {
+    pub product_id: String,
+    pub stage: SupplyChainStage,
+    pub location: String,
+    pub status: EventStatus,
+    pub details: String,
+    pub certifications: Vec<String>,
+    pub estimated_arrival: Option<u64>,
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum SupplyChainStage {
     RawMaterialSourcing,
     Manufacturing,
@@ -146,7 +417,7 @@ pub enum SupplyChainStage {
     Retail,
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum EventStatus {
     Pending,
     InProgress,
@@ -154,9 +425,280 @@ pub enum EventStatus {
     Failed,
 }
 
+// Stages a product may move to after its latest event reached `from_stage` with `from_status`
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct StageTransitionRule {
+    pub from_stage: SupplyChainStage,
+    pub from_status: EventStatus,
+    pub to_stages: Vec<SupplyChainStage>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct StageTransitionRules(pub Vec<StageTransitionRule>);
+
+// Error returned when a stage update is rejected. Transition errors are typed
+// so clients can offer the stages that are allowed instead; other rejections
+// (permissions, certifications, missing records) carry a message.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum SupplyChainError {
+    ProductRecalled {
+        product_id: String,
+    },
+    InvalidTransition {
+        product_id: String,
+        from: SupplyChainStage,
+        from_status: EventStatus,
+        to: SupplyChainStage,
+        allowed: Vec<SupplyChainStage>,
+    },
+    Rejected(String),
+}
+
+impl From<String> for SupplyChainError {
+    fn from(message: String) -> Self {
+        SupplyChainError::Rejected(message)
+    }
+}
+
+impl std::fmt::Display for SupplyChainError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SupplyChainError::ProductRecalled { product_id } => {
+                write!(f, "Invalid transition: product {} has been recalled", product_id)
+            }
+            SupplyChainError::InvalidTransition { product_id, from, from_status, to, allowed } => write!(
+                f,
+                "Invalid transition: product {} cannot move from {:?} ({:?}) to {:?}; allowed: {:?}",
+                product_id, from, from_status, to, allowed
+            ),
+            SupplyChainError::Rejected(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct HistoryVerification {
+    pub product_id: String,
+    pub valid: bool,
+    pub event_count: u64,
+    pub head_hash: Option<String>,
+    // First event whose stored hash or link to its predecessor does not match
+    pub first_invalid_event: Option<String>,
+}
+
+// Lot Types
+// Goods that move together: a production lot, a pallet split from it or a mixed
+// shipment merged from several pallets
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Lot {
+    pub id: String,
+    pub product_ids: Vec<String>,
+    pub quantity: f64,
+    pub unit: String,
+    pub batch_number: Option<String>,
+    pub location: String,
+    // Splits, merges and lot events are recorded by the holder
+    pub holder: Principal,
+    pub status: LotStatus,
+    // Lots this one was split or merged from
+    pub parent_ids: Vec<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+}
+
+// Split and merged lots are closed; their goods live on in the child lots
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum LotStatus {
+    Active,
+    Split,
+    Merged,
+}
+
+// Genealogy edge from a lot to a lot created from it
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LotEdge {
+    pub parent_id: String,
+    pub child_id: String,
+    pub operation: LotOperation,
+    // Quantity of the parent that went into the child
+    pub quantity: f64,
+    pub recorded_by: Principal,
+    pub recorded_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum LotOperation {
+    Split,
+    Merge,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LotRequest {
+    pub product_ids: Vec<String>,
+    pub quantity: f64,
+    pub unit: String,
+    pub batch_number: Option<String>,
+    pub location: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LotPortion {
+    pub quantity: f64,
+    // Products of the parent lot in this portion; empty keeps all of them. Listed
+    // portions must not share products and together must cover the parent lot.
+    pub product_ids: Vec<String>,
+}
+
+// A stage update recorded for every product in a lot
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LotEventInput {
+    pub stage: SupplyChainStage,
+    pub location: String,
+    pub status: EventStatus,
+    pub details: String,
+    pub certifications: Vec<String>,
+    pub estimated_arrival: Option<u64>,
+    pub metadata: HashMap<String, String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LotGenealogy {
+    pub lot: Lot,
+    // Edges leading to the lot, nearest first
+    pub ancestors: Vec<LotEdge>,
+    // Edges leading away from the lot, nearest first
+    pub descendants: Vec<LotEdge>,
+}
+
+// Certified Query Types
+// `witness` is a CBOR hash tree whose root equals the certified data in
+// `certificate`; it contains the leaf `products/<product_id>` = head hash.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CertifiedProduct {
+    pub product: ProductWithHistory,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CertifiedSupplyChainEvents {
+    pub product_id: String,
+    pub events: Vec<SupplyChainEvent>,
+    pub head_hash: Option<String>,
+    pub certificate: Vec<u8>,
+    pub witness: Vec<u8>,
+}
+
+// HTTP Gateway Types
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+// Recall Types
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Recall {
+    pub id: String,
+    pub reason: String,
+    pub severity: RecallSeverity,
+    pub product_ids: Vec<String>,
+    pub batch_number: Option<String>,
+    pub status: RecallStatus,
+    pub initiated_by: Principal,
+    pub acknowledgements: Vec<RecallAcknowledgement>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub closed_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RecallRequest {
+    pub reason: String,
+    pub severity: RecallSeverity,
+    pub product_ids: Vec<String>,
+    pub batch_number: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RecallAcknowledgement {
+    pub actor: String,
+    pub actor_id: Principal,
+    pub acknowledged_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RecallSeverity {
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RecallStatus {
+    Open,
+    InProgress,
+    Closed,
+}
+
 // Partner Types
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct Partner {
+    pub id: String,
+    // User who registered the partner and maintains its details
+    pub manager_id: Principal,
+    pub company_name: String,
+    pub partner_type: PartnerType,
+    pub contact_email: String,
+    pub contact_person: String,
+    pub certifications: Vec<String>,
+    pub status: PartnerStatus,
+    // Oldest first, starting with the registration
+    pub status_history: Vec<PartnerStatusChange>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub reputation_score: u32,
+    pub certification_alerts: Option<Vec<CertificationAlert>>,
+}
+
+// Removed partners are kept so their history stays available
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PartnerStatus {
+    Pending,
+    Verified,
+    Suspended,
+    Removed,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PartnerStatusChange {
+    pub status: PartnerStatus,
+    pub changed_by: Principal,
+    pub changed_at: u64,
+    pub reason: Option<String>,
+}
+
+// Contact details editable through update_partner; None leaves a field unchanged
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PartnerUpdate {
+    pub contact_email: Option<String>,
+    pub contact_person: Option<String>,
+}
+
+// Layout before partners had their own IDs (storage versions 0-7), keyed by
+// the registering principal; only read during migration
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PartnerV1 {
     pub id: Principal,
     pub company_name: String,
     pub partner_type: PartnerType,
@@ -177,7 +719,7 @@ pub struct PartnerRegistration {
     pub certifications: Vec<String>,
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub enum PartnerType {
     Manufacturer,
     Supplier,
@@ -187,6 +729,276 @@ pub enum PartnerType {
     CertificationBody,
 }
 
+// Certification Types
+// Issued by a verified certification body partner. Products and events refer to
+// certifications by ID.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Certification {
+    pub id: String,
+    // Standard certified against, e.g. "FairTrade" or "ISO 9001"
+    pub standard: String,
+    pub scope: String,
+    pub issuer_id: String,
+    pub subject: CertificationSubject,
+    pub valid_from: u64,
+    pub valid_until: u64,
+    pub issued_by: Principal,
+    pub issued_at: u64,
+    pub revoked_at: Option<u64>,
+    pub revocation_reason: Option<String>,
+    // Last state the certification scan acted on, so each change is propagated once
+    pub notified_state: Option<CertificationState>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum CertificationState {
+    // Lapses within the warning period
+    Expiring,
+    Expired,
+    Revoked,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CertificationAlert {
+    pub certification_id: String,
+    pub state: CertificationState,
+    pub valid_until: u64,
+    pub flagged_at: u64,
+}
+
+// Notification Types
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Notification {
+    pub id: u64,
+    pub recipient: Principal,
+    pub certification_id: String,
+    pub state: CertificationState,
+    pub message: String,
+    // IDs of the holder's products and partners the certification applies to
+    pub affected: Vec<String>,
+    pub created_at: u64,
+    pub read: bool,
+}
+
+// Subjects are bound to records their holders cannot claim by naming them:
+// batches to the manufacturer or organisation that produces them, partners to
+// their manager and organisation, facilities to a registered facility
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CertificationSubject {
+    Product(String),
+    Batch {
+        batch_number: String,
+        manufacturer: CertificationHolder,
+    },
+    // Partner ID
+    Partner(String),
+    // Facility ID
+    Facility(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CertificationHolder {
+    Principal(Principal),
+    Organisation(String),
+}
+
+// Certification layout before subjects were bound to their holders
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CertificationV1 {
+    pub id: String,
+    pub standard: String,
+    pub scope: String,
+    pub issuer_id: String,
+    pub subject: CertificationSubjectV1,
+    pub valid_from: u64,
+    pub valid_until: u64,
+    pub issued_by: Principal,
+    pub issued_at: u64,
+    pub revoked_at: Option<u64>,
+    pub revocation_reason: Option<String>,
+    pub notified_state: Option<CertificationState>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum CertificationSubjectV1 {
+    Product(String),
+    Batch(String),
+    Partner(String),
+    Facility(String),
+}
+
+// Batch and facility certifications named their subject by free text, which
+// cannot be tied to a holder; they are revoked and have to be reissued
+impl From<CertificationV1> for Certification {
+    fn from(certification: CertificationV1) -> Self {
+        let unbound = matches!(
+            certification.subject,
+            CertificationSubjectV1::Batch(_) | CertificationSubjectV1::Facility(_)
+        );
+        let subject = match certification.subject {
+            CertificationSubjectV1::Product(product_id) => CertificationSubject::Product(product_id),
+            CertificationSubjectV1::Partner(partner_id) => CertificationSubject::Partner(partner_id),
+            CertificationSubjectV1::Batch(batch_number) => CertificationSubject::Batch {
+                batch_number,
+                manufacturer: CertificationHolder::Principal(certification.issued_by),
+            },
+            CertificationSubjectV1::Facility(facility) => CertificationSubject::Facility(facility),
+        };
+        let (revoked_at, revocation_reason) = match certification.revoked_at {
+            None if unbound => (
+                Some(certification.issued_at),
+                Some("Subject is not bound to a holder; reissue the certification".to_string()),
+            ),
+            revoked_at => (revoked_at, certification.revocation_reason),
+        };
+
+        Certification {
+            id: certification.id,
+            standard: certification.standard,
+            scope: certification.scope,
+            issuer_id: certification.issuer_id,
+            subject,
+            valid_from: certification.valid_from,
+            valid_until: certification.valid_until,
+            issued_by: certification.issued_by,
+            issued_at: certification.issued_at,
+            revoked_at,
+            revocation_reason,
+            notified_state: certification.notified_state,
+        }
+    }
+}
+
+// A site whose certifications only its owner can reference, at its location
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Facility {
+    pub id: String,
+    pub name: String,
+    pub location: String,
+    pub owner: CertificationHolder,
+    pub registered_by: Principal,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct FacilityRequest {
+    pub name: String,
+    pub location: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CertificationRequest {
+    // Partner ID of the issuing certification body
+    pub issuer_id: String,
+    pub standard: String,
+    pub scope: String,
+    pub subject: CertificationSubject,
+    pub valid_from: u64,
+    pub valid_until: u64,
+}
+
+// Ethical Scoring Types
+// Policies are immutable once stored; each change creates a new version, so the
+// parameters behind any version stay known. Scores are always computed from the
+// product's current events, recalls and certification alerts.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ScoringPolicy {
+    pub version: u32,
+    // Starting score for products without a declared sustainability_score
+    pub base_score: f64,
+    pub certification_weights: Vec<CertificationWeight>,
+    // Points for product certifications without an explicit weight
+    pub default_certification_points: f64,
+    // Points per stage update recorded with certifications
+    pub certified_event_points: f64,
+    pub failed_event_penalty: f64,
+    pub recall_penalty: f64,
+    pub raw_material_risks: Vec<RawMaterialRisk>,
+    // Points for product certifications that expired or were revoked. None keeps
+    // scoring them like valid ones, as policies before lapse tracking did.
+    pub lapsed_certification_points: Option<f64>,
+    pub created_by: Principal,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ScoringPolicyInput {
+    pub base_score: f64,
+    pub certification_weights: Vec<CertificationWeight>,
+    pub default_certification_points: f64,
+    pub certified_event_points: f64,
+    pub failed_event_penalty: f64,
+    pub recall_penalty: f64,
+    pub raw_material_risks: Vec<RawMaterialRisk>,
+    pub lapsed_certification_points: Option<f64>,
+}
+
+// Matched case-insensitively against the product's certifications
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CertificationWeight {
+    pub certification: String,
+    pub points: f64,
+}
+
+// Applies once when any raw material contains `material` (case-insensitive)
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RawMaterialRisk {
+    pub material: String,
+    pub penalty: f64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ScoreContribution {
+    // What the points are for, e.g. "certification:FairTrade" or "event:EVT-0000001A"
+    pub source: String,
+    pub description: String,
+    pub points: f64,
+}
+
+// `score` is `uncapped_score` clamped to 0..=100
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EthicalScoreBreakdown {
+    pub product_id: String,
+    pub policy_version: u32,
+    pub score: f64,
+    pub uncapped_score: f64,
+    pub contributions: Vec<ScoreContribution>,
+}
+
+// Reputation Types
+// Running counts behind a partner's reputation score
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReputationStats {
+    pub events_recorded: u64,
+    pub completed_events: u64,
+    pub failed_events: u64,
+    pub certified_events: u64,
+    // Stage updates with an estimated_arrival that the next stage update settled
+    pub deliveries_with_eta: u64,
+    pub on_time_deliveries: u64,
+    pub recalls_traced: u64,
+    pub ratings_count: u64,
+    pub ratings_total: u64,
+}
+
+// `value` is in 0..=1 and contributes `value * weight` points to the score
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReputationFactor {
+    pub name: String,
+    pub weight: u32,
+    pub value: f64,
+    pub contribution: f64,
+    pub sample_size: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReputationBreakdown {
+    pub partner_id: String,
+    pub score: u32,
+    pub factors: Vec<ReputationFactor>,
+    pub stats: ReputationStats,
+}
+
 // Analytics Types
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct AnalyticsData {
@@ -208,67 +1020,154 @@ pub struct CanisterStatus {
     pub uptime: u64,
 }
 
-// Wrapper type for Vec<SupplyChainEvent> to implement Storable
+// Pre-v2 storage layout holding a product's whole history; only read during migration
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
-pub struct SupplyChainEventList(pub Vec<SupplyChainEvent>);
+pub struct SupplyChainEventList(pub Vec<SupplyChainEventV1>);
 
-// Implement Storable for stable storage
-impl Storable for User {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
-    }
+// Versioned stable storage. Version 0 is the bare Candid encoding used before
+// the envelope existed; the current structs only added optional fields since, so
+// those payloads decode directly.
+impl VersionedRecord for User {
+    const SCHEMA_VERSION: u8 = 1;
 
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+    fn migrate(version: u8, payload: &[u8]) -> Self {
+        match version {
+            0 | 1 => decode_payload(payload),
+            _ => unsupported_version::<Self>(version),
+        }
     }
+}
+
+impl VersionedRecord for Product {
+    const SCHEMA_VERSION: u8 = 1;
 
-    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 1024,
-        is_fixed_size: false,
-    };
+    fn migrate(version: u8, payload: &[u8]) -> Self {
+        match version {
+            0 | 1 => decode_payload(payload),
+            _ => unsupported_version::<Self>(version),
+        }
+    }
 }
 
-impl Storable for Product {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
+impl VersionedRecord for SupplyChainEventList {
+    const SCHEMA_VERSION: u8 = 1;
+
+    fn migrate(version: u8, payload: &[u8]) -> Self {
+        match version {
+            0 | 1 => decode_payload(payload),
+            _ => unsupported_version::<Self>(version),
+        }
     }
+}
 
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+impl VersionedRecord for SupplyChainEvent {
+    const SCHEMA_VERSION: u8 = 2;
+
+    fn migrate(version: u8, payload: &[u8]) -> Self {
+        match version {
+            1 => decode_payload::<SupplyChainEventV1>(payload).into(),
+            2 => decode_payload(payload),
+            _ => unsupported_version::<Self>(version),
+        }
     }
+}
 
-    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 2048,
-        is_fixed_size: false,
-    };
+impl VersionedRecord for StageTransitionRules {
+    const SCHEMA_VERSION: u8 = 1;
 }
 
-impl Storable for SupplyChainEventList {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
-    }
+impl VersionedRecord for Recall {
+    const SCHEMA_VERSION: u8 = 1;
+}
+
+impl VersionedRecord for ProductChange {
+    const SCHEMA_VERSION: u8 = 1;
+}
+
+impl VersionedRecord for ProductAccess {
+    const SCHEMA_VERSION: u8 = 2;
 
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+    fn migrate(version: u8, payload: &[u8]) -> Self {
+        match version {
+            1 => decode_payload::<ProductAccessV1>(payload).into(),
+            2 => decode_payload(payload),
+            _ => unsupported_version::<Self>(version),
+        }
     }
+}
 
-    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 8192,
-        is_fixed_size: false,
-    };
+impl VersionedRecord for Partner {
+    const SCHEMA_VERSION: u8 = 1;
 }
 
-impl Storable for Partner {
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(candid::encode_one(self).unwrap())
-    }
+impl VersionedRecord for Certification {
+    const SCHEMA_VERSION: u8 = 2;
 
-    fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        candid::decode_one(&bytes).unwrap()
+    fn migrate(version: u8, payload: &[u8]) -> Self {
+        match version {
+            1 => decode_payload::<CertificationV1>(payload).into(),
+            2 => decode_payload(payload),
+            _ => unsupported_version::<Self>(version),
+        }
     }
+}
+
+impl VersionedRecord for Facility {
+    const SCHEMA_VERSION: u8 = 1;
+}
+
+impl VersionedRecord for Lot {
+    const SCHEMA_VERSION: u8 = 1;
+}
+
+impl VersionedRecord for LotEdge {
+    const SCHEMA_VERSION: u8 = 1;
+}
+
+impl VersionedRecord for Notification {
+    const SCHEMA_VERSION: u8 = 1;
+}
+
+impl VersionedRecord for ScoringPolicy {
+    const SCHEMA_VERSION: u8 = 1;
+}
+
+impl VersionedRecord for ReputationStats {
+    const SCHEMA_VERSION: u8 = 1;
+}
 
-    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Bounded {
-        max_size: 1024,
-        is_fixed_size: false,
-    };
+impl VersionedRecord for Organisation {
+    const SCHEMA_VERSION: u8 = 1;
 }
+
+impl VersionedRecord for PartnerV1 {
+    const SCHEMA_VERSION: u8 = 1;
+
+    fn migrate(version: u8, payload: &[u8]) -> Self {
+        match version {
+            0 | 1 => decode_payload(payload),
+            _ => unsupported_version::<Self>(version),
+        }
+    }
+}
+
+impl_versioned_storable!(
+    User,
+    Product,
+    SupplyChainEventList,
+    SupplyChainEvent,
+    StageTransitionRules,
+    Recall,
+    Partner,
+    ProductChange,
+    ProductAccess,
+    Organisation,
+    PartnerV1,
+    ReputationStats,
+    ScoringPolicy,
+    Certification,
+    Facility,
+    Notification,
+    Lot,
+    LotEdge,
+);
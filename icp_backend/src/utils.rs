@@ -1,26 +1,293 @@
-use ic_cdk::api::time;
+use crate::types::{EventKind, EventStatus, SupplyChainEvent, SupplyChainStage};
 use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 
-pub fn generate_product_id(category: &str) -> String {
-    let timestamp = time();
+const NANOS_PER_DAY: u64 = 86_400 * 1_000_000_000;
+
+// previous_hash of the first event in every product history
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// Product IDs keep the `CT-YYYY-XXXXXX` shape. The suffix hashes a
+// per-canister counter with a random salt, so IDs are not guessable, but 24
+// bits collide within a few thousand IDs; callers must skip IDs already in use.
+pub fn generate_product_id(timestamp: u64, sequence: u64, salt: &[u8]) -> String {
+    let hash = hash_sequence("product", sequence, salt);
+    let hash_part = hash[..3].iter().fold(0u32, |acc, &b| acc * 256 + b as u32);
+
+    format!("CT-{}-{:06X}", year_from_timestamp(timestamp), hash_part)
+}
+
+pub fn generate_event_id(sequence: u64, salt: &[u8]) -> String {
+    let hash = hash_sequence("event", sequence, salt);
+
+    format!("EVT-{:08X}",
+        hash[..4].iter().fold(0u32, |acc, &b| acc * 256 + b as u32)
+    )
+}
+
+fn hash_sequence(namespace: &str, sequence: u64, salt: &[u8]) -> [u8; 32] {
     let mut hasher = Sha256::new();
-    hasher.update(format!("{}-{}", category, timestamp));
-    let hash = hasher.finalize();
-    
-    // Create a simple timestamp-based ID
-    let year = 2024; // You can calculate this from timestamp if needed
-    let hash_part = &hash[..3].iter().fold(0u32, |acc, &b| acc * 256 + b as u32);
-    
-    format!("CT-{}-{:06X}", year, hash_part)
-}
-
-pub fn generate_event_id() -> String {
-    let timestamp = time();
+    hasher.update(salt);
+    hasher.update(namespace.as_bytes());
+    hasher.update(sequence.to_be_bytes());
+    hasher.finalize().into()
+}
+
+// Gregorian year of an IC timestamp (nanoseconds since the Unix epoch)
+pub fn year_from_timestamp(timestamp: u64) -> i64 {
+    civil_date_from_timestamp(timestamp).0
+}
+
+// Case, punctuation and spacing do not make a different company: "ACME, Inc."
+// and "Acme Inc" both normalise to "acme inc"
+pub fn normalize_company_name(name: &str) -> String {
+    name.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// "YYYY-MM-DD" (UTC) for an IC timestamp
+pub fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_date_from_timestamp(timestamp);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Days-to-civil conversion from Howard Hinnant's date algorithms
+fn civil_date_from_timestamp(timestamp: u64) -> (i64, u32, u32) {
+    let days = (timestamp / NANOS_PER_DAY) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400;
+
+    // Months are counted from March, so January and February belong to the next year
+    (if month <= 2 { year + 1 } else { year }, month, day)
+}
+
+// Hashes the event's fields in a fixed order, each length-prefixed, with metadata
+// sorted by key. Fields added to events later must only be fed in when set so
+// that hashes of existing events stay reproducible.
+//
+// Enums are encoded as explicit tags so that renaming or reordering variants
+// cannot change a hash: the stage and status are one byte each (see stage_tag
+// and status_tag), and the kind is a one-byte tag followed by its fields, with
+// principals as their raw bytes. Texts are UTF-8 and integers big-endian u64.
+pub fn compute_event_hash(event: &SupplyChainEvent) -> String {
     let mut hasher = Sha256::new();
-    hasher.update(timestamp.to_string());
-    let hash = hasher.finalize();
-    
-    format!("EVT-{:08X}", 
-        &hash[..4].iter().fold(0u32, |acc, &b| acc * 256 + b as u32)
-    )
+    let mut field = |bytes: &[u8]| {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    };
+
+    field(event.previous_hash.as_bytes());
+    field(event.id.as_bytes());
+    field(event.product_id.as_bytes());
+    field(&[stage_tag(&event.stage)]);
+    field(event.location.as_bytes());
+    field(&event.timestamp.to_be_bytes());
+    field(event.actor.as_bytes());
+    field(event.actor_id.as_slice());
+    field(&[status_tag(&event.status)]);
+    field(event.details.as_bytes());
+    field(&(event.certifications.len() as u64).to_be_bytes());
+    for certification in &event.certifications {
+        field(certification.as_bytes());
+    }
+    match event.estimated_arrival {
+        Some(arrival) => field(&arrival.to_be_bytes()),
+        None => field(&[]),
+    }
+    let metadata: BTreeMap<&String, &String> = event.metadata.iter().collect();
+    field(&(metadata.len() as u64).to_be_bytes());
+    for (key, value) in metadata {
+        field(key.as_bytes());
+        field(value.as_bytes());
+    }
+    match &event.kind {
+        Some(EventKind::Recall { recall_id }) => {
+            field(&[1]);
+            field(recall_id.as_bytes());
+        }
+        Some(EventKind::Amendment { amends, reason }) => {
+            field(&[2]);
+            field(amends.as_bytes());
+            field(reason.as_bytes());
+        }
+        Some(EventKind::HandoffInitiated { to }) => {
+            field(&[3]);
+            field(to.as_slice());
+        }
+        Some(EventKind::HandoffAccepted { from }) => {
+            field(&[4]);
+            field(from.as_slice());
+        }
+        None => {}
+    }
+    if let Some(lot_id) = &event.lot_id {
+        field(lot_id.as_bytes());
+    }
+
+    to_hex(&hasher.finalize())
+}
+
+// Hash tags of the stages and statuses. Existing values must never change; new
+// variants take the next free number.
+fn stage_tag(stage: &SupplyChainStage) -> u8 {
+    match stage {
+        SupplyChainStage::RawMaterialSourcing => 0,
+        SupplyChainStage::Manufacturing => 1,
+        SupplyChainStage::QualityControl => 2,
+        SupplyChainStage::Packaging => 3,
+        SupplyChainStage::Shipping => 4,
+        SupplyChainStage::Distribution => 5,
+        SupplyChainStage::Retail => 6,
+    }
+}
+
+fn status_tag(status: &EventStatus) -> u8 {
+    match status {
+        EventStatus::Pending => 0,
+        EventStatus::InProgress => 1,
+        EventStatus::Completed => 2,
+        EventStatus::Failed => 3,
+    }
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Maps an f64 onto a u64 with the same ordering, so scores and prices can be
+// compared as zero-padded integers
+pub fn sortable_f64(value: f64) -> u64 {
+    let bits = value.to_bits();
+    if bits >> 63 == 1 {
+        !bits
+    } else {
+        bits | (1 << 63)
+    }
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+    use std::collections::HashMap;
+
+    fn event() -> SupplyChainEvent {
+        SupplyChainEvent {
+            id: "EVT-00000001".to_string(),
+            product_id: "CT-2024-000001".to_string(),
+            stage: SupplyChainStage::Shipping,
+            location: "Rotterdam".to_string(),
+            timestamp: 1_700_000_000_000_000_000,
+            actor: "Acme".to_string(),
+            actor_id: Principal::from_slice(&[1, 2, 3]),
+            status: EventStatus::InProgress,
+            details: "Loaded".to_string(),
+            certifications: vec!["CERT-1".to_string()],
+            estimated_arrival: Some(1_700_100_000_000_000_000),
+            metadata: HashMap::from([
+                ("vessel".to_string(), "Ever Given".to_string()),
+                ("container".to_string(), "MSCU1234567".to_string()),
+            ]),
+            kind: Some(EventKind::HandoffInitiated { to: Principal::from_slice(&[9, 9]) }),
+            lot_id: Some("LOT-1".to_string()),
+            previous_hash: GENESIS_HASH.to_string(),
+            hash: String::new(),
+            superseded_by: None,
+        }
+    }
+
+    #[test]
+    fn event_hash_follows_documented_encoding() {
+        let mut encoding = Vec::new();
+        let mut field = |bytes: &[u8]| {
+            encoding.extend((bytes.len() as u64).to_be_bytes());
+            encoding.extend(bytes);
+        };
+        field(GENESIS_HASH.as_bytes());
+        field(b"EVT-00000001");
+        field(b"CT-2024-000001");
+        field(&[4]);
+        field(b"Rotterdam");
+        field(&1_700_000_000_000_000_000u64.to_be_bytes());
+        field(b"Acme");
+        field(&[1, 2, 3]);
+        field(&[1]);
+        field(b"Loaded");
+        field(&1u64.to_be_bytes());
+        field(b"CERT-1");
+        field(&1_700_100_000_000_000_000u64.to_be_bytes());
+        field(&2u64.to_be_bytes());
+        field(b"container");
+        field(b"MSCU1234567");
+        field(b"vessel");
+        field(b"Ever Given");
+        field(&[3]);
+        field(&[9, 9]);
+        field(b"LOT-1");
+
+        assert_eq!(compute_event_hash(&event()), to_hex(&Sha256::digest(&encoding)));
+    }
+
+    #[test]
+    fn event_hash_changes_with_link_and_tagged_fields() {
+        let original = compute_event_hash(&event());
+        let changes: [fn(&mut SupplyChainEvent); 6] = [
+            |event| event.previous_hash = "1".repeat(64),
+            |event| event.stage = SupplyChainStage::Distribution,
+            |event| event.status = EventStatus::Completed,
+            |event| event.kind = Some(EventKind::HandoffInitiated { to: Principal::from_slice(&[9, 8]) }),
+            |event| event.kind = None,
+            |event| {
+                event.metadata.insert("seal".to_string(), "42".to_string());
+            },
+        ];
+
+        for change in changes {
+            let mut changed = event();
+            change(&mut changed);
+            assert_ne!(compute_event_hash(&changed), original);
+        }
+    }
+
+    #[test]
+    fn superseded_by_is_not_hashed() {
+        let mut amended = event();
+        amended.superseded_by = Some("EVT-00000002".to_string());
+
+        assert_eq!(compute_event_hash(&amended), compute_event_hash(&event()));
+    }
 }
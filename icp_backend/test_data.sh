@@ -21,39 +21,79 @@ fi
 echo "📋 Using canister ID: $CANISTER_ID"
 echo ""
 
-# Function to register a user
+# Every seeded user gets its own dfx identity; the current identity is the
+# bootstrap admin set up by deploy.sh and approves their role requests
+ADMIN_IDENTITY=$(dfx identity whoami)
+
+# Extracts the text returned in `variant { Ok = "..." }`
+ok_text() {
+    sed -n 's/.*Ok = "\([^"]*\)".*/\1/p'
+}
+
+# Function to register a user under its own identity and approve the requested role
 register_user() {
-    local email=$1
-    local first_name=$2
-    local last_name=$3
-    local company=$4
-    local role=$5
+    local identity=$1
+    local email=$2
+    local first_name=$3
+    local last_name=$4
+    local company=$5
+    local role=$6
     
     echo "👤 Registering user: $first_name $last_name ($email)"
-    dfx canister call supply_chain_backend register_user "(record { 
+    dfx identity new "$identity" --storage-mode plaintext > /dev/null 2>&1 || true
+    dfx --identity "$identity" canister call supply_chain_backend register_user "(record { 
         email=\"$email\"; 
         first_name=\"$first_name\"; 
         last_name=\"$last_name\"; 
         company=\"$company\"; 
         role=variant { $role } 
     })"
+    local principal=$(dfx --identity "$identity" identity get-principal)
+    dfx --identity "$ADMIN_IDENTITY" canister call supply_chain_backend approve_role_request "(principal \"$principal\", true)"
     echo ""
 }
 
-# Function to register a product
+# Issues a batch certification per standard (separated by ";") to the
+# manufacturer behind `identity` and prints the certification IDs as a Candid vec
+issue_batch_certifications() {
+    local identity=$1
+    local batch=$2
+    local standards=$3
+    local manufacturer=$(dfx --identity "$identity" identity get-principal)
+    local ids=""
+    
+    IFS=';' read -ra standard_list <<< "$standards"
+    for standard in "${standard_list[@]}"; do
+        local id=$(dfx --identity "$ADMIN_IDENTITY" canister call supply_chain_backend issue_certification "(record { 
+            issuer_id=\"$CERTIFICATION_BODY_ID\"; 
+            standard=\"$standard\"; 
+            scope=\"Batch $batch\"; 
+            subject=variant { Batch = record { batch_number=\"$batch\"; manufacturer=variant { Principal = principal \"$manufacturer\" } } }; 
+            valid_from=1704067200000000000; 
+            valid_until=1893456000000000000 
+        })" | ok_text)
+        ids="$ids \"$id\";"
+    done
+    echo "vec {$ids }"
+}
+
+# Function to register a product under the manufacturer's identity, with batch
+# certifications from the registry for the given standards
 register_product() {
-    local name=$1
-    local category=$2
-    local description=$3
-    local batch=$4
-    local location=$5
-    local materials=$6
-    local certs=$7
-    local sustainability=$8
-    local value=$9
+    local identity=$1
+    local name=$2
+    local category=$3
+    local description=$4
+    local batch=$5
+    local location=$6
+    local materials=$7
+    local standards=$8
+    local sustainability=$9
+    local value=${10}
     
     echo "📦 Registering product: $name"
-    dfx canister call supply_chain_backend register_product "(record { 
+    local certs=$(issue_batch_certifications "$identity" "$batch" "$standards")
+    dfx --identity "$identity" canister call supply_chain_backend register_product "(record { 
         name=\"$name\"; 
         category=\"$category\"; 
         description=opt \"$description\"; 
@@ -72,182 +112,215 @@ register_product() {
 echo "🔥 Registering test users..."
 echo "================================"
 
+# Admin User (the bootstrap admin is made Admin directly)
+echo "👤 Registering admin: $ADMIN_IDENTITY"
+dfx --identity "$ADMIN_IDENTITY" canister call supply_chain_backend register_user "(record { 
+    email=\"admin@suptrus.com\"; 
+    first_name=\"Admin\"; 
+    last_name=\"User\"; 
+    company=\"SupTrus Platform\"; 
+    role=variant { Admin } 
+})"
+echo ""
+
 # Manufacturer Users
-register_user "john.doe@ecotextiles.com" "John" "Doe" "EcoTextiles Ltd." "Manufacturer"
-register_user "sarah.green@organicfarms.com" "Sarah" "Green" "Organic Farms Co." "Manufacturer"
-register_user "mike.chen@techcorp.com" "Mike" "Chen" "TechCorp Electronics" "Manufacturer"
+register_user "seed-john" "john.doe@ecotextiles.com" "John" "Doe" "EcoTextiles Ltd." "Manufacturer"
+register_user "seed-sarah" "sarah.green@organicfarms.com" "Sarah" "Green" "Organic Farms Co." "Manufacturer"
+register_user "seed-mike" "mike.chen@techcorp.com" "Mike" "Chen" "TechCorp Electronics" "Manufacturer"
 
 # Logistics Users
-register_user "lisa.transport@globalship.com" "Lisa" "Transport" "GlobalShip Logistics" "LogisticsProvider"
-register_user "carlos.freight@fasttrack.com" "Carlos" "Freight" "FastTrack Shipping" "LogisticsProvider"
+register_user "seed-lisa" "lisa.transport@globalship.com" "Lisa" "Transport" "GlobalShip Logistics" "LogisticsProvider"
+register_user "seed-carlos" "carlos.freight@fasttrack.com" "Carlos" "Freight" "FastTrack Shipping" "LogisticsProvider"
 
 # Retailer Users
-register_user "emma.retail@ecostore.com" "Emma" "Retail" "EcoStore Chain" "Retailer"
-register_user "david.sales@techmart.com" "David" "Sales" "TechMart Retail" "Retailer"
+register_user "seed-emma" "emma.retail@ecostore.com" "Emma" "Retail" "EcoStore Chain" "Retailer"
+register_user "seed-david" "david.sales@techmart.com" "David" "Sales" "TechMart Retail" "Retailer"
 
 # Quality Assurance Users
-register_user "anna.quality@certifyplus.com" "Anna" "Quality" "CertifyPlus QA" "QualityAssurance"
+register_user "seed-anna" "anna.quality@certifyplus.com" "Anna" "Quality" "CertifyPlus QA" "QualityAssurance"
 
 # Supply Chain Manager
-register_user "robert.manager@supplyhub.com" "Robert" "Manager" "SupplyHub Solutions" "SupplyChainManager"
-
-# Admin User
-register_user "admin@suptrus.com" "Admin" "User" "SupTrus Platform" "Admin"
+register_user "seed-robert" "robert.manager@supplyhub.com" "Robert" "Manager" "SupplyHub Solutions" "SupplyChainManager"
 
 echo "✅ User registration completed!"
 echo ""
 
-# Wait a moment for users to be processed
-sleep 2
+# Certifications referenced by products must come from the registry, issued by
+# a verified certification body
+echo "🔥 Registering certification body..."
+echo "================================"
+CERTIFICATION_BODY_ID=$(dfx --identity "$ADMIN_IDENTITY" canister call supply_chain_backend register_partner "(record { 
+    company_name=\"CertifyPlus\"; 
+    partner_type=variant { CertificationBody }; 
+    contact_email=\"registry@certifyplus.com\"; 
+    contact_person=\"Anna Quality\"; 
+    certifications=vec {} 
+})" | ok_text)
+dfx --identity "$ADMIN_IDENTITY" canister call supply_chain_backend verify_partner "(\"$CERTIFICATION_BODY_ID\", opt \"Seed certification body\")"
+echo "✅ Certification body $CERTIFICATION_BODY_ID verified!"
+echo ""
 
 echo "🔥 Registering test products..."
 echo "================================"
 
 # Apparel Products
 register_product \
+    "seed-john" \
     "Organic Cotton T-Shirt" \
     "Apparel" \
     "100% organic cotton t-shirt with natural dyes" \
     "BATCH-TEX-001" \
     "Mumbai, India" \
     "vec { \"Organic Cotton\"; \"Natural Dyes\"; \"Recycled Polyester\" }" \
-    "vec { \"GOTS Certified\"; \"Fair Trade\"; \"OEKO-TEX Standard 100\" }" \
+    "GOTS Certified;Fair Trade;OEKO-TEX Standard 100" \
     "95.0" \
     "25.99"
 
 register_product \
+    "seed-john" \
     "Bamboo Fiber Hoodie" \
     "Apparel" \
     "Sustainable bamboo fiber hoodie with organic cotton blend" \
     "BATCH-TEX-002" \
     "Bangalore, India" \
     "vec { \"Bamboo Fiber\"; \"Organic Cotton\"; \"Natural Dyes\" }" \
-    "vec { \"GOTS Certified\"; \"Cradle to Cradle\" }" \
+    "GOTS Certified;Cradle to Cradle" \
     "92.0" \
     "45.99"
 
 register_product \
+    "seed-john" \
     "Recycled Denim Jeans" \
     "Apparel" \
     "Jeans made from 80% recycled denim and organic cotton" \
     "BATCH-TEX-003" \
     "Guatemala City, Guatemala" \
     "vec { \"Recycled Denim\"; \"Organic Cotton\"; \"Eco-friendly Dyes\" }" \
-    "vec { \"Fair Trade\"; \"B Corp Certified\" }" \
+    "Fair Trade;B Corp Certified" \
     "88.0" \
     "65.99"
 
 # Electronics Products
 register_product \
+    "seed-mike" \
     "Solar Power Bank" \
     "Electronics" \
     "Portable solar-powered charging device with recycled materials" \
     "BATCH-ELEC-001" \
     "Shenzhen, China" \
     "vec { \"Recycled Aluminum\"; \"Solar Cells\"; \"Lithium Battery\" }" \
-    "vec { \"RoHS Compliant\"; \"Energy Star\"; \"ISO 14001\" }" \
+    "RoHS Compliant;Energy Star;ISO 14001" \
     "85.0" \
     "89.99"
 
 register_product \
+    "seed-mike" \
     "Biodegradable Phone Case" \
     "Electronics" \
     "Compostable phone case made from plant-based materials" \
     "BATCH-ELEC-002" \
     "San Francisco, USA" \
     "vec { \"PLA Bioplastic\"; \"Bamboo Fiber\"; \"Natural Pigments\" }" \
-    "vec { \"Biodegradable\"; \"Non-toxic\" }" \
+    "Biodegradable;Non-toxic" \
     "90.0" \
     "19.99"
 
 register_product \
+    "seed-mike" \
     "Refurbished Laptop" \
     "Electronics" \
     "Professionally refurbished laptop with extended warranty" \
     "BATCH-ELEC-003" \
     "Austin, USA" \
     "vec { \"Recycled Components\"; \"New Battery\"; \"Refurbished Parts\" }" \
-    "vec { \"EPEAT Gold\"; \"Energy Star\"; \"Warranty Certified\" }" \
+    "EPEAT Gold;Energy Star;Warranty Certified" \
     "78.0" \
     "599.99"
 
 # Food & Beverage Products
 register_product \
+    "seed-sarah" \
     "Fair Trade Coffee Beans" \
     "Food & Beverage" \
     "Single-origin arabica coffee beans from sustainable farms" \
     "BATCH-FOOD-001" \
     "Medellín, Colombia" \
     "vec { \"Arabica Coffee Beans\"; \"Natural Processing\" }" \
-    "vec { \"Fair Trade\"; \"Organic\"; \"Rainforest Alliance\" }" \
+    "Fair Trade;Organic;Rainforest Alliance" \
     "94.0" \
     "12.99"
 
 register_product \
+    "seed-sarah" \
     "Organic Quinoa" \
     "Food & Beverage" \
     "Premium organic quinoa from high-altitude farms" \
     "BATCH-FOOD-002" \
     "La Paz, Bolivia" \
     "vec { \"Organic Quinoa\"; \"Natural Packaging\" }" \
-    "vec { \"Organic\"; \"Fair Trade\"; \"Non-GMO\" }" \
+    "Organic;Fair Trade;Non-GMO" \
     "91.0" \
     "8.99"
 
 register_product \
+    "seed-sarah" \
     "Sustainable Honey" \
     "Food & Beverage" \
     "Raw honey from bee-friendly sustainable apiaries" \
     "BATCH-FOOD-003" \
     "Auckland, New Zealand" \
     "vec { \"Raw Honey\"; \"Glass Packaging\" }" \
-    "vec { \"Organic\"; \"Bee-Friendly\"; \"Sustainable Packaging\" }" \
+    "Organic;Bee-Friendly;Sustainable Packaging" \
     "89.0" \
     "15.99"
 
 # Cosmetics Products
 register_product \
+    "seed-sarah" \
     "Natural Face Cream" \
     "Cosmetics" \
     "Organic face cream with plant-based ingredients" \
     "BATCH-COSM-001" \
     "Provence, France" \
     "vec { \"Organic Shea Butter\"; \"Jojoba Oil\"; \"Lavender Extract\" }" \
-    "vec { \"Organic\"; \"Cruelty-Free\"; \"Vegan\" }" \
+    "Organic;Cruelty-Free;Vegan" \
     "93.0" \
     "29.99"
 
 register_product \
+    "seed-sarah" \
     "Zero-Waste Shampoo Bar" \
     "Cosmetics" \
     "Solid shampoo bar with minimal packaging" \
     "BATCH-COSM-002" \
     "Portland, USA" \
     "vec { \"Coconut Oil\"; \"Essential Oils\"; \"Natural Surfactants\" }" \
-    "vec { \"Zero Waste\"; \"Plastic-Free\"; \"Biodegradable\" }" \
+    "Zero Waste;Plastic-Free;Biodegradable" \
     "96.0" \
     "12.99"
 
 # Home & Garden Products
 register_product \
+    "seed-mike" \
     "Bamboo Cutting Board" \
     "Home & Garden" \
     "Sustainable bamboo cutting board with natural finish" \
     "BATCH-HOME-001" \
     "Hangzhou, China" \
     "vec { \"Bamboo\"; \"Natural Oil Finish\" }" \
-    "vec { \"FSC Certified\"; \"Sustainable Harvesting\" }" \
+    "FSC Certified;Sustainable Harvesting" \
     "87.0" \
     "24.99"
 
 register_product \
+    "seed-mike" \
     "Solar Garden Lights" \
     "Home & Garden" \
     "LED garden lights powered by solar energy" \
     "BATCH-HOME-002" \
     "Munich, Germany" \
     "vec { \"Solar Panels\"; \"LED Lights\"; \"Recycled Plastic\" }" \
-    "vec { \"Energy Efficient\"; \"Weather Resistant\"; \"RoHS Compliant\" }" \
+    "Energy Efficient;Weather Resistant;RoHS Compliant" \
     "82.0" \
     "39.99"
 
@@ -269,9 +342,9 @@ echo ""
 echo "🎉 Test data population completed successfully!"
 echo ""
 echo "📚 What you can do now:"
-echo "1. Search products: dfx canister call supply_chain_backend search_products '(record { name=opt \"Cotton\"; category=null; manufacturer=null; status=null; limit=opt 10 })'"
+echo "1. Search products: dfx canister call supply_chain_backend search_products '(record { name=opt \"Cotton\"; category=null; manufacturer=null; manufacturer_id=null; organisation_id=null; status=null; batch_number=null; production_date_from=null; production_date_to=null; updated_from=null; updated_to=null; certifications=null; raw_materials=null; location=null; sort_by=null; sort_order=null; limit=opt 10; cursor=null })'"
 echo "2. Get specific product: dfx canister call supply_chain_backend get_product '(\"CT-2024-XXXXXX\")'"
-echo "3. View all partners: dfx canister call supply_chain_backend get_partners"
+echo "3. View all partners: dfx canister call supply_chain_backend get_partners '(null)'"
 echo "4. Check user info: dfx canister call supply_chain_backend get_user"
 echo ""
 echo "🌐 Frontend Integration:"